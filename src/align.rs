@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Length of one envelope window used for alignment.
+pub const FRAME_MS: u32 = 10;
+
+/// Shortest silence taken to be a pause between sentences.
+const MIN_PAUSE_MS: u32 = 120;
+
+/// Windows quieter than this fraction of the chunk's peak level count as silence.
+const SILENCE_LEVEL: f32 = 0.08;
+
+/// When and where one sentence is heard in the chapter audio.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SentenceTiming {
+    /// Index of the chunk (TTS request) the sentence was spoken in.
+    pub chunk: usize,
+    /// Start and end in seconds from the start of the chapter audio.
+    pub start: f64,
+    pub end: f64,
+    /// True when both ends were placed on pauses found in the audio; false when at least one
+    /// end was estimated from the sentence lengths.
+    pub aligned: bool,
+    pub text: String,
+}
+
+/// Place each sentence of one chunk within its audio.
+///
+/// Gemini returns no timing metadata, so this is a local, energy-based aligner: pauses are
+/// found in the loudness `envelope` (windows of [`FRAME_MS`]) and matched, in order, to the
+/// sentence boundaries expected from each sentence's share of the text. Boundaries without a
+/// nearby pause are interpolated between their matched neighbours. With no envelope the chunk's
+/// time is shared out by sentence length. `start` and `duration` place the chunk in the chapter.
+pub fn align_sentences(
+    chunk: usize,
+    sentences: &[String],
+    envelope: Option<&[f32]>,
+    start: f64,
+    duration: f64,
+) -> Vec<SentenceTiming> {
+    let n = sentences.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut cum = vec![0usize; n + 1];
+    for (k, s) in sentences.iter().enumerate() {
+        cum[k + 1] = cum[k] + s.chars().count().max(1);
+    }
+    let total = cum[n] as f64;
+    let frame = FRAME_MS as f64 / 1000.0;
+
+    // Boundary k sits before sentence k: (end of the previous sentence, start of this one),
+    // in envelope windows, plus whether it was found in the audio
+    let mut bounds: Vec<Option<(f64, f64)>> = vec![None; n + 1];
+    let mut found = vec![false; n + 1];
+    let detected = envelope.and_then(|env| speech_span(env).map(|span| (env, span)));
+    let (speech_start, speech_end) = match detected {
+        Some((_, span)) => span,
+        None => (0.0, duration / frame),
+    };
+    bounds[0] = Some((speech_start, speech_start));
+    bounds[n] = Some((speech_end, speech_end));
+    found[0] = detected.is_some();
+    found[n] = detected.is_some();
+
+    if let Some((env, _)) = detected {
+        let pauses = pauses(env, speech_start as usize, speech_end as usize);
+        let span = speech_end - speech_start;
+        let tolerance = (span / n as f64 * 0.5).max(500.0 / FRAME_MS as f64);
+        let mut next_pause = 0usize;
+        for k in 1..n {
+            let expected = speech_start + span * cum[k] as f64 / total;
+            let best = pauses[next_pause..]
+                .iter()
+                .enumerate()
+                .map(|(i, &(a, b))| (i + next_pause, a, b, ((a + b) / 2.0 - expected).abs()))
+                .filter(|&(_, _, _, d)| d <= tolerance)
+                .min_by(|x, y| x.3.total_cmp(&y.3));
+            if let Some((i, a, b, _)) = best {
+                bounds[k] = Some((a, b));
+                found[k] = true;
+                next_pause = i + 1;
+            }
+        }
+    }
+
+    // Interpolate the remaining boundaries between known ones, by character position
+    for k in 1..n {
+        if bounds[k].is_some() {
+            continue;
+        }
+        let prev = (0..k).rev().find(|&j| bounds[j].is_some()).unwrap_or(0);
+        let next = (k + 1..=n).find(|&j| bounds[j].is_some()).unwrap_or(n);
+        let from = bounds[prev].map_or(speech_start, |(_, b)| b);
+        let to = bounds[next].map_or(speech_end, |(a, _)| a);
+        let share = (cum[k] - cum[prev]) as f64 / (cum[next] - cum[prev]).max(1) as f64;
+        let at = from + (to - from) * share;
+        bounds[k] = Some((at, at));
+    }
+
+    let to_secs = |w: f64| (start + w * frame).min(start + duration);
+    (0..n)
+        .map(|k| {
+            let (_, from) = bounds[k].unwrap_or_default();
+            let (to, _) = bounds[k + 1].unwrap_or_default();
+            SentenceTiming {
+                chunk,
+                start: to_secs(from),
+                end: to_secs(to.max(from)),
+                aligned: found[k] && found[k + 1],
+                text: sentences[k].clone(),
+            }
+        })
+        .collect()
+}
+
+/// Number of leading words used to find a paragraph among the timed sentences.
+const MATCH_WORDS: usize = 4;
+
+/// Index of the first timing at or after `from` whose sentence is where the Markdown block
+/// `markdown` starts being read: a sentence opening with the block's first words, or for a
+/// heading, a sentence ending with the heading's words (as its spoken announcement does).
+pub fn find_sentence(
+    timings: &[SentenceTiming],
+    from: usize,
+    markdown: &str,
+    heading: bool,
+) -> Option<usize> {
+    let all = words(&strip_markup(markdown));
+    if all.is_empty() {
+        return None;
+    }
+    let key: Vec<String> = all.iter().take(MATCH_WORDS).cloned().collect();
+    (from..timings.len()).find(|&i| {
+        let sentence = words(&timings[i].text);
+        if heading {
+            sentence.ends_with(&all)
+        } else {
+            sentence.starts_with(&key)
+        }
+    })
+}
+
+/// Write the chapter's sentence timing map as JSON.
+pub fn write_timing_map(path: &Path, timings: &[SentenceTiming]) -> Result<()> {
+    let json = serde_json::to_string_pretty(timings).context("failed to serialize timing map")?;
+    fs::write(path, json).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn silence_threshold(envelope: &[f32]) -> f32 {
+    let peak = envelope.iter().copied().fold(0f32, f32::max);
+    peak * SILENCE_LEVEL
+}
+
+/// First and last audible window (end exclusive), or None for a silent chunk.
+fn speech_span(envelope: &[f32]) -> Option<(f64, f64)> {
+    let threshold = silence_threshold(envelope);
+    let first = envelope.iter().position(|&v| v > threshold)?;
+    let last = envelope.iter().rposition(|&v| v > threshold)?;
+    Some((first as f64, (last + 1) as f64))
+}
+
+/// Silent runs of at least [`MIN_PAUSE_MS`] between `from` and `to`, as (start, end) windows.
+fn pauses(envelope: &[f32], from: usize, to: usize) -> Vec<(f64, f64)> {
+    let threshold = silence_threshold(envelope);
+    let min_len = (MIN_PAUSE_MS / FRAME_MS) as usize;
+    let mut out = Vec::new();
+    let mut run_start: Option<usize> = None;
+    for (i, &v) in envelope.iter().enumerate().take(to).skip(from) {
+        match (v <= threshold, run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(s)) => {
+                if i - s >= min_len {
+                    out.push((s as f64, i as f64));
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// `[text](url)` -> `text` and `<span>text</span>` -> `text`, so link targets and tag
+/// names don't count as words.
+fn strip_markup(line: &str) -> String {
+    let re_markup = Regex::new(r"\]\([^)]*\)|\]\[[^\]]*\]|</?[A-Za-z][^>]*>").unwrap();
+    re_markup.replace_all(line, "]").into_owned()
+}
+
+/// Lowercase alphanumeric words, ignoring Markdown punctuation.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|w| w.trim_matches('\'').to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
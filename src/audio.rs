@@ -1,4 +1,9 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::align::FRAME_MS;
 
 pub fn guess_audio_extension(mime: &str) -> &'static str {
     match mime {
@@ -16,186 +21,754 @@ pub fn guess_audio_extension(mime: &str) -> &'static str {
     }
 }
 
-pub fn merge_mp3(parts: &[&[u8]]) -> Vec<u8> {
-    // Simple byte concatenation; most players handle back-to-back MP3 frames.
-    merge_concat(parts)
-}
-
-pub fn merge_concat(parts: &[&[u8]]) -> Vec<u8> {
-    let total: usize = parts.iter().map(|p| p.len()).sum();
-    let mut out = Vec::with_capacity(total);
-    for p in parts {
-        out.extend_from_slice(p);
-    }
-    out
+/// MIME type for an audio file extension (`mp3` or `.mp3`), the inverse of
+/// [`guess_audio_extension`]. None for extensions that aren't audio.
+pub fn audio_mime_for_extension(ext: &str) -> Option<&'static str> {
+    const MIME_TYPES: &[&str] = &["audio/mpeg", "audio/wav", "audio/ogg", "audio/flac"];
+    let ext = format!(".{}", ext.trim_start_matches('.').to_ascii_lowercase());
+    MIME_TYPES
+        .iter()
+        .copied()
+        .find(|m| guess_audio_extension(m) == ext)
 }
 
-pub fn try_merge_wav(parts: &[&[u8]]) -> Result<Vec<u8>> {
-    // Parse each WAV, validate same format, and concatenate data chunks; emit new header
-    if parts.is_empty() {
-        return Ok(Vec::new());
-    }
+/// `wFormatTag` values this module decodes.
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
 
-    let mut data_blobs: Vec<&[u8]> = Vec::with_capacity(parts.len());
-    let mut total_data_len: usize = 0;
+/// Tail shared by the `KSDATAFORMAT_SUBTYPE_*` GUIDs; the sub-format GUID of an extensible
+/// WAV is the plain format tag followed by these bytes.
+const KSDATAFORMAT_SUBTYPE_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
 
-    let (fmt, fmt_size) = parse_wav_fmt(parts[0])?;
-    let first_data = parse_wav_data(parts[0])?;
-    data_blobs.push(first_data);
-    total_data_len += first_data.len();
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct WavFmt {
+    audio_format: u16, // 1 = PCM, 3 = IEEE float, 0xFFFE = extensible
+    num_channels: u16,
+    sample_rate: u32,
+    byte_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+    /// Bytes of the `fmt ` chunk after the first 16 (`cbSize` and, for extensible WAVs, the
+    /// valid bits, channel mask and sub-format GUID), written back unchanged.
+    extension: Vec<u8>,
+}
 
-    for wav in &parts[1..] {
-        let (fmt_n, _fmt_size_n) = parse_wav_fmt(wav)?;
-        if fmt != fmt_n {
-            return Err(anyhow!("WAV format mismatch across chunks"));
+impl WavFmt {
+    /// The sample encoding: the format tag, or for an extensible WAV the tag its sub-format
+    /// GUID stands for.
+    fn sample_format(&self) -> u16 {
+        if self.audio_format == WAVE_FORMAT_EXTENSIBLE
+            && let Some(guid) = self.extension.get(8..24)
+            && guid[2..] == KSDATAFORMAT_SUBTYPE_TAIL
+        {
+            return u16::from_le_bytes([guid[0], guid[1]]);
         }
-        let d = parse_wav_data(wav)?;
-        data_blobs.push(d);
-        total_data_len += d.len();
+        self.audio_format
     }
 
-    let mut out = Vec::with_capacity(44 + total_data_len);
-    write_wav_header(&mut out, &fmt, fmt_size, total_data_len)?;
-    for blob in data_blobs {
-        out.extend_from_slice(blob);
+    /// Size of the `fmt ` chunk body.
+    fn chunk_size(&self) -> u32 {
+        16 + self.extension.len() as u32
     }
-    Ok(out)
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct WavFmt {
-    audio_format: u16, // 1 = PCM, 3 = IEEE float
-    num_channels: u16,
-    sample_rate: u32,
-    byte_rate: u32,
-    block_align: u16,
-    bits_per_sample: u16,
+fn parse_wav_fmt(bytes: &[u8]) -> Result<WavFmt> {
+    let body = find_wav_chunk(bytes, b"fmt ")?.ok_or_else(|| anyhow!("fmt chunk not found"))?;
+    if body.len() < 16 {
+        return Err(anyhow!("fmt chunk too small"));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes(body[i..i + 2].try_into().unwrap());
+    let u32_at = |i: usize| u32::from_le_bytes(body[i..i + 4].try_into().unwrap());
+    let fmt = WavFmt {
+        audio_format: u16_at(0),
+        num_channels: u16_at(2),
+        sample_rate: u32_at(4),
+        byte_rate: u32_at(8),
+        block_align: u16_at(12),
+        bits_per_sample: u16_at(14),
+        extension: body[16..].to_vec(),
+    };
+    Ok(fmt)
 }
 
-fn parse_wav_fmt(bytes: &[u8]) -> Result<(WavFmt, u32)> {
-    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+fn parse_wav_data(bytes: &[u8]) -> Result<&[u8]> {
+    find_wav_chunk(bytes, b"data")?.ok_or_else(|| anyhow!("data chunk not found"))
+}
+
+/// Body of the first chunk with `id` in a RIFF, RF64 or BW64 WAVE file.
+///
+/// RF64 (EBU Tech 3306) and BW64 (ITU-R BS.2088) files start with a `ds64` chunk holding
+/// 64-bit sizes; chunks whose 32-bit size is 0xFFFFFFFF take theirs from it (`data` from its
+/// data size, others from its table).
+fn find_wav_chunk<'a>(bytes: &'a [u8], id: &[u8; 4]) -> Result<Option<&'a [u8]>> {
+    if bytes.len() < 12 || &bytes[8..12] != b"WAVE" {
         return Err(anyhow!("invalid WAV header"));
     }
+    let is_64 = match &bytes[0..4] {
+        b"RIFF" => false,
+        b"RF64" | b"BW64" => true,
+        _ => return Err(anyhow!("invalid WAV header")),
+    };
+
+    // (chunk id, 64-bit size) from ds64: the data size first, then any table entries
+    let mut sizes64: Vec<([u8; 4], u64)> = Vec::new();
     let mut off = 12usize;
-    let mut fmt: Option<(WavFmt, u32)> = None;
-    while off + 8 <= bytes.len() {
-        let id = &bytes[off..off + 4];
-        let sz = u32::from_le_bytes(bytes[off + 4..off + 8].try_into().unwrap());
-        let chunk_data_start = off + 8;
-        let chunk_data_end = chunk_data_start + sz as usize;
-        if chunk_data_end > bytes.len() {
-            break;
+    if is_64 {
+        if bytes.len() < 20 || &bytes[12..16] != b"ds64" {
+            return Err(anyhow!("RF64 file without ds64 chunk"));
         }
-        if id == b"fmt " {
-            if sz < 16 {
-                return Err(anyhow!("fmt chunk too small"));
-            }
-            let audio_format = u16::from_le_bytes(
-                bytes[chunk_data_start..chunk_data_start + 2]
-                    .try_into()
-                    .unwrap(),
-            );
-            let num_channels = u16::from_le_bytes(
-                bytes[chunk_data_start + 2..chunk_data_start + 4]
-                    .try_into()
-                    .unwrap(),
-            );
-            let sample_rate = u32::from_le_bytes(
-                bytes[chunk_data_start + 4..chunk_data_start + 8]
-                    .try_into()
-                    .unwrap(),
-            );
-            let byte_rate = u32::from_le_bytes(
-                bytes[chunk_data_start + 8..chunk_data_start + 12]
-                    .try_into()
-                    .unwrap(),
-            );
-            let block_align = u16::from_le_bytes(
-                bytes[chunk_data_start + 12..chunk_data_start + 14]
-                    .try_into()
-                    .unwrap(),
-            );
-            let bits_per_sample = u16::from_le_bytes(
-                bytes[chunk_data_start + 14..chunk_data_start + 16]
-                    .try_into()
-                    .unwrap(),
-            );
-            fmt = Some((
-                WavFmt {
-                    audio_format,
-                    num_channels,
-                    sample_rate,
-                    byte_rate,
-                    block_align,
-                    bits_per_sample,
-                },
-                sz,
+        let sz = u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize;
+        let ds64 = bytes
+            .get(20..20 + sz)
+            .filter(|d| d.len() >= DS64_BODY_LEN as usize)
+            .ok_or_else(|| anyhow!("ds64 chunk too small"))?;
+        let u64_at = |i: usize| u64::from_le_bytes(ds64[i..i + 8].try_into().unwrap());
+        sizes64.push((*b"data", u64_at(8)));
+        let table_len = u32::from_le_bytes(ds64[24..28].try_into().unwrap()) as usize;
+        for entry in ds64[28..].chunks_exact(12).take(table_len) {
+            let entry_id: [u8; 4] = entry[0..4].try_into().unwrap();
+            sizes64.push((
+                entry_id,
+                u64::from_le_bytes(entry[4..12].try_into().unwrap()),
             ));
-            break;
         }
-        off = chunk_data_end + (sz as usize % 2); // chunks are word-aligned
+        off = 20 + sz + sz % 2;
     }
-    fmt.ok_or_else(|| anyhow!("fmt chunk not found"))
-}
 
-fn parse_wav_data(bytes: &[u8]) -> Result<&[u8]> {
-    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
-        return Err(anyhow!("invalid WAV header"));
-    }
-    let mut off = 12usize;
     while off + 8 <= bytes.len() {
-        let id = &bytes[off..off + 4];
-        let sz = u32::from_le_bytes(bytes[off + 4..off + 8].try_into().unwrap());
+        let chunk_id = &bytes[off..off + 4];
+        let sz32 = u32::from_le_bytes(bytes[off + 4..off + 8].try_into().unwrap());
+        let sz = if is_64 && sz32 == u32::MAX {
+            sizes64
+                .iter()
+                .find(|(entry_id, _)| entry_id == chunk_id)
+                .map(|&(_, size)| size)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no ds64 size for chunk {}",
+                        String::from_utf8_lossy(chunk_id)
+                    )
+                })?
+        } else {
+            sz32 as u64
+        };
         let chunk_data_start = off + 8;
-        let chunk_data_end = chunk_data_start + sz as usize;
-        if chunk_data_end > bytes.len() {
+        let Some(chunk_data_end) = usize::try_from(sz)
+            .ok()
+            .and_then(|sz| chunk_data_start.checked_add(sz))
+            .filter(|&end| end <= bytes.len())
+        else {
             break;
+        };
+        if chunk_id == id {
+            return Ok(Some(&bytes[chunk_data_start..chunk_data_end]));
         }
-        if id == b"data" {
-            return Ok(&bytes[chunk_data_start..chunk_data_end]);
-        }
-        off = chunk_data_end + (sz as usize % 2);
+        off = chunk_data_end + (chunk_data_end - chunk_data_start) % 2; // chunks are word-aligned
     }
-    Err(anyhow!("data chunk not found"))
+    Ok(None)
 }
 
-fn write_wav_header(out: &mut Vec<u8>, fmt: &WavFmt, fmt_size: u32, data_len: usize) -> Result<()> {
-    let fmt_size = if fmt_size < 16 { 16 } else { fmt_size };
-    let riff_chunk_size: u32 = 4 + (8 + fmt_size) + (8 + (data_len as u32));
-
-    out.extend_from_slice(b"RIFF");
-    out.extend_from_slice(&riff_chunk_size.to_le_bytes());
-    out.extend_from_slice(b"WAVE");
+/// RF64 `ds64` chunk with the 64-bit RIFF and data sizes and no table entries.
+fn ds64_chunk(fmt: &WavFmt, riff_size: u64, data_len: u64) -> Vec<u8> {
+    let mut ds64 = Vec::with_capacity(8 + DS64_BODY_LEN as usize);
+    ds64.extend_from_slice(b"ds64");
+    ds64.extend_from_slice(&DS64_BODY_LEN.to_le_bytes());
+    ds64.extend_from_slice(&riff_size.to_le_bytes());
+    ds64.extend_from_slice(&data_len.to_le_bytes());
+    let sample_count = data_len / fmt.block_align.max(1) as u64;
+    ds64.extend_from_slice(&sample_count.to_le_bytes());
+    ds64.extend_from_slice(&0u32.to_le_bytes()); // table length
+    ds64
+}
 
-    // fmt chunk
+fn write_fmt_chunk(out: &mut Vec<u8>, fmt: &WavFmt) {
     out.extend_from_slice(b"fmt ");
-    out.extend_from_slice(&fmt_size.to_le_bytes());
+    out.extend_from_slice(&fmt.chunk_size().to_le_bytes());
     out.extend_from_slice(&fmt.audio_format.to_le_bytes());
     out.extend_from_slice(&fmt.num_channels.to_le_bytes());
     out.extend_from_slice(&fmt.sample_rate.to_le_bytes());
     out.extend_from_slice(&fmt.byte_rate.to_le_bytes());
     out.extend_from_slice(&fmt.block_align.to_le_bytes());
     out.extend_from_slice(&fmt.bits_per_sample.to_le_bytes());
-    if fmt_size > 16 {
-        // pad extra fmt bytes with zeros
-        out.resize(out.len() + (fmt_size as usize - 16), 0);
+    out.extend_from_slice(&fmt.extension);
+    if fmt.extension.len() % 2 == 1 {
+        out.push(0); // chunks are word-aligned
+    }
+}
+
+/// Body length of the RF64 `ds64` chunk (RIFF size, data size, sample count, table length).
+/// A `JUNK` chunk of this size is reserved after `WAVE` so the header can become RF64 in place.
+const DS64_BODY_LEN: u32 = 28;
+
+/// How the parts handed to a [`ChapterAudioWriter`] are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PartInput {
+    /// Raw little-endian PCM samples (Gemini's `audio/L16;codec=pcm;rate=24000`), written
+    /// straight into a WAV as they arrive.
+    Pcm,
+    /// Complete WAV files, whose data chunks are appended.
+    WavFiles,
+    /// Anything else, concatenated as is.
+    Bytes,
+}
+
+/// What was measured while a part was written: its size and, for WAV output, its playing
+/// time, the share of near-silent samples and its loudness envelope (windows of
+/// [`FRAME_MS`]).
+#[derive(Clone, Debug, Default)]
+pub struct PartStats {
+    pub bytes: u64,
+    pub duration: Option<f64>,
+    pub silence_ratio: Option<f32>,
+    pub envelope: Option<Vec<f32>>,
+}
+
+/// Writes a chapter's audio to disk as it arrives, so memory use stays flat however long
+/// the chapter or its parts are. Raw PCM fragments and WAV parts are merged into one WAV:
+/// their samples are appended and the RIFF and data sizes are patched in
+/// [`ChapterAudioWriter::finish`], which switches the file to RF64 when it outgrows the
+/// 4 GiB limit of 32-bit sizes. Other formats are concatenated (MP3 frames play back to
+/// back). The file is written as `<name>.part` and renamed when finished, so an interrupted
+/// chapter never looks complete.
+///
+/// Each part is bracketed by [`begin_part`](Self::begin_part) and
+/// [`end_part`](Self::end_part); a part that turns out unusable (mostly silent) can be cut
+/// off again with [`discard_part`](Self::discard_part) and regenerated.
+pub struct ChapterAudioWriter {
+    file: BufWriter<File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    mime: String,
+    input: PartInput,
+    /// Format of the merged WAV and the offset of its data size field; None for other formats.
+    wav: Option<(WavFmt, u64)>,
+    /// File offset where the audio data starts.
+    data_start: u64,
+    data_len: u64,
+    title: Option<String>,
+    parts: usize,
+    /// Data length when the current (or last ended) part began.
+    part_start: u64,
+    /// Whether the current part has been started but not ended.
+    part_open: bool,
+    meter: Option<PcmMeter>,
+}
+
+impl ChapterAudioWriter {
+    /// Start `dir/<stem><ext>` with the chapter's first part (or first fragment of it).
+    /// Raw PCM (`mime` such as `audio/L16;rate=24000`) and WAV parts produce a WAV; MP3 gets
+    /// an ID3 title tag up front.
+    pub fn create(
+        dir: &Path,
+        stem: &str,
+        first_part: &[u8],
+        mime: &str,
+        title: Option<&str>,
+    ) -> Result<Self> {
+        let path = dir.join(format!("{}{}", stem, guess_audio_extension(mime)));
+        let tmp_path = PathBuf::from(format!("{}.part", path.display()));
+        let mut file = BufWriter::new(
+            File::create(&tmp_path)
+                .with_context(|| format!("failed to create {}", tmp_path.display()))?,
+        );
+
+        let lower = mime.to_ascii_lowercase();
+        let mut wav = None;
+        let mut data_start = 0u64;
+        let mut out_mime = mime.to_string();
+        let input = if is_raw_linear_pcm(mime) {
+            PartInput::Pcm
+        } else if lower.contains("wav") {
+            PartInput::WavFiles
+        } else {
+            PartInput::Bytes
+        };
+        let fmt = match input {
+            PartInput::Pcm => {
+                out_mime = "audio/wav".to_string();
+                Some(pcm_wav_fmt(
+                    parse_sample_rate(mime).unwrap_or(24000),
+                    parse_channels(mime).unwrap_or(1),
+                    parse_sample_bits(mime).unwrap_or(16),
+                )?)
+            }
+            PartInput::WavFiles => Some(parse_wav_fmt(first_part)?),
+            PartInput::Bytes => None,
+        };
+        if let Some(fmt) = fmt {
+            let mut header = Vec::with_capacity(64 + fmt.extension.len());
+            header.extend_from_slice(b"RIFF");
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(b"JUNK");
+            header.extend_from_slice(&DS64_BODY_LEN.to_le_bytes());
+            header.resize(header.len() + DS64_BODY_LEN as usize, 0);
+            write_fmt_chunk(&mut header, &fmt);
+            header.extend_from_slice(b"data");
+            let data_size_at = header.len() as u64;
+            header.extend_from_slice(&0u32.to_le_bytes());
+            file.write_all(&header)?;
+            data_start = header.len() as u64;
+            wav = Some((fmt, data_size_at));
+        } else if lower.contains("mpeg") || lower.contains("mp3") {
+            if let Some(title) = title {
+                let tag = mp3_title_tag(title);
+                file.write_all(&tag)?;
+                data_start = tag.len() as u64;
+            }
+        } else {
+            eprintln!(
+                "warn: unsupported mime '{}' for merging; concatenating raw bytes (may not play correctly)",
+                mime
+            );
+        }
+
+        let mut writer = Self {
+            file,
+            tmp_path,
+            path,
+            mime: out_mime,
+            input,
+            wav,
+            data_start,
+            data_len: 0,
+            title: title.map(str::to_string),
+            parts: 0,
+            part_start: 0,
+            part_open: false,
+            meter: None,
+        };
+        writer.begin_part();
+        writer.write_part(first_part)?;
+        Ok(writer)
+    }
+
+    /// Whether the output is a WAV (so silence can be inserted and durations are known).
+    pub fn is_wav(&self) -> bool {
+        self.wav.is_some()
+    }
+
+    /// MIME type of the output file (`audio/wav` for raw PCM input).
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    /// Number of parts written, including inserted silences.
+    pub fn parts(&self) -> usize {
+        self.parts
+    }
+
+    /// Start a new part; the bytes written from here on belong to it.
+    pub fn begin_part(&mut self) {
+        self.part_start = self.data_len;
+        self.part_open = true;
+        self.meter = self
+            .wav
+            .as_ref()
+            .map(|(fmt, _)| PcmMeter::new(fmt, FRAME_MS));
+    }
+
+    /// Write the next piece of the current part: a PCM fragment, a whole WAV (which must
+    /// have the same format as the first) or raw bytes, matching how the writer was created.
+    pub fn write_part(&mut self, bytes: &[u8]) -> Result<()> {
+        let data = match (self.input, &self.wav) {
+            (PartInput::WavFiles, Some((fmt, _))) => {
+                let part_fmt = parse_wav_fmt(bytes)?;
+                if *fmt != part_fmt {
+                    return Err(anyhow!("WAV format mismatch across chunks"));
+                }
+                parse_wav_data(bytes)?
+            }
+            _ => bytes,
+        };
+        self.file.write_all(data)?;
+        self.data_len += data.len() as u64;
+        if let Some(meter) = self.meter.as_mut() {
+            meter.push(data);
+        }
+        Ok(())
+    }
+
+    /// Finish the current part and return its measurements.
+    pub fn end_part(&mut self) -> PartStats {
+        self.parts += 1;
+        self.part_open = false;
+        let bytes = self.data_len - self.part_start;
+        let Some((meter, (fmt, _))) = self.meter.take().zip(self.wav.as_ref()) else {
+            return PartStats {
+                bytes,
+                ..PartStats::default()
+            };
+        };
+        PartStats {
+            bytes,
+            duration: (fmt.byte_rate > 0).then(|| bytes as f64 / fmt.byte_rate as f64),
+            silence_ratio: meter.silence_ratio(),
+            envelope: meter.envelope(),
+        }
+    }
+
+    /// Cut the last part (ended or not) off the file again, e.g. to regenerate it.
+    pub fn discard_part(&mut self) -> Result<()> {
+        if !self.part_open {
+            self.parts = self.parts.saturating_sub(1);
+        }
+        self.part_open = false;
+        self.file.flush()?;
+        let file = self.file.get_mut();
+        file.set_len(self.data_start + self.part_start)?;
+        file.seek(SeekFrom::End(0))?;
+        self.data_len = self.part_start;
+        self.meter = None;
+        Ok(())
+    }
+
+    /// Append `ms` milliseconds of silence to a WAV and return its length in seconds.
+    pub fn append_silence(&mut self, ms: u32) -> Result<f64> {
+        let Some((fmt, _)) = &self.wav else {
+            return Err(anyhow!("silence can only be inserted into WAV output"));
+        };
+        let frames = fmt.sample_rate as u64 * ms as u64 / 1000;
+        let data_len = frames * fmt.block_align as u64;
+        // Unsigned 8-bit PCM is centred on 0x80; everything else on zero
+        let fill = if fmt.sample_format() == WAVE_FORMAT_PCM && fmt.bits_per_sample == 8 {
+            0x80
+        } else {
+            0
+        };
+        self.file.write_all(&vec![fill; data_len as usize])?;
+        self.data_len += data_len;
+        self.parts += 1;
+        Ok(if fmt.byte_rate == 0 {
+            0.0
+        } else {
+            data_len as f64 / fmt.byte_rate as f64
+        })
+    }
+
+    /// Write the title tag and final sizes, move the file into place, and return its path
+    /// and size in bytes.
+    pub fn finish(self) -> Result<(PathBuf, u64)> {
+        let Self {
+            file,
+            tmp_path,
+            path,
+            wav,
+            data_len,
+            title,
+            ..
+        } = self;
+        let mut file = file
+            .into_inner()
+            .map_err(|e| anyhow!("failed to write {}: {}", tmp_path.display(), e.error()))?;
+
+        if let Some((fmt, data_size_at)) = wav {
+            // Chunks are word-aligned; the pad byte isn't counted in the data size
+            if data_len % 2 == 1 {
+                file.write_all(&[0])?;
+            }
+            if let Some(title) = &title {
+                file.write_all(&wav_title_chunk(title))?;
+            }
+            let riff_size = file.stream_position()? - 8;
+            if riff_size <= u32::MAX as u64 && data_len <= u32::MAX as u64 {
+                file.seek(SeekFrom::Start(4))?;
+                file.write_all(&(riff_size as u32).to_le_bytes())?;
+                file.seek(SeekFrom::Start(data_size_at))?;
+                file.write_all(&(data_len as u32).to_le_bytes())?;
+            } else {
+                // RF64: 32-bit sizes are set to 0xFFFFFFFF and the real ones go in ds64,
+                // which takes the place of the reserved JUNK chunk
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(b"RF64")?;
+                file.write_all(&u32::MAX.to_le_bytes())?;
+                file.seek(SeekFrom::Start(12))?;
+                file.write_all(&ds64_chunk(&fmt, riff_size, data_len))?;
+                file.seek(SeekFrom::Start(data_size_at))?;
+                file.write_all(&u32::MAX.to_le_bytes())?;
+            }
+        }
+        file.sync_all()
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        let size = file.metadata()?.len();
+        drop(file);
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("failed to move {} into place", path.display()))?;
+        Ok((path, size))
+    }
+}
+
+/// Decoder from one little-endian sample to a -1.0..1.0 value, for 8-bit (unsigned),
+/// 16/24/32-bit integer PCM and 32-bit float. Extensible WAVs are decoded by sub-format;
+/// samples are read at their container size, so 24 valid bits in 32 work too.
+fn sample_decoder(fmt: &WavFmt) -> Result<fn(&[u8]) -> f32> {
+    Ok(match (fmt.sample_format(), fmt.bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (WAVE_FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        (WAVE_FORMAT_PCM, 24) => {
+            |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0
+        }
+        (WAVE_FORMAT_PCM, 32) => {
+            |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (format, bits) => {
+            return Err(anyhow!(
+                "unsupported WAV sample format {} with {} bits",
+                format,
+                bits
+            ));
+        }
+    })
+}
+
+/// Playing time of a WAV file on disk, read from its header without loading the samples.
+/// Chunk headers are walked with seeks, so RF64/BW64 files beyond 4 GiB cost a few reads.
+/// A data chunk cut short (e.g. a file still being written) counts up to the end of the file.
+pub fn wav_duration_from_path(path: &Path) -> Result<f64> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let file_len = file.metadata()?.len();
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)
+        .map_err(|_| anyhow!("invalid WAV header"))?;
+    if &header[8..12] != b"WAVE" {
+        return Err(anyhow!("invalid WAV header"));
+    }
+    let is_64 = match &header[0..4] {
+        b"RIFF" => false,
+        b"RF64" | b"BW64" => true,
+        _ => return Err(anyhow!("invalid WAV header")),
+    };
+
+    let mut byte_rate: Option<u32> = None;
+    let mut data_size64: Option<u64> = None;
+    let mut off = 12u64;
+    while off + 8 <= file_len {
+        let mut chunk = [0u8; 8];
+        file.seek(SeekFrom::Start(off))?;
+        file.read_exact(&mut chunk)?;
+        let sz32 = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+        let body_start = off + 8;
+        match &chunk[0..4] {
+            b"ds64" if is_64 => {
+                let mut ds64 = [0u8; 16];
+                file.read_exact(&mut ds64)?;
+                data_size64 = Some(u64::from_le_bytes(ds64[8..16].try_into().unwrap()));
+            }
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                file.read_exact(&mut fmt)?;
+                byte_rate = Some(u32::from_le_bytes(fmt[8..12].try_into().unwrap()));
+            }
+            b"data" => {
+                let byte_rate = byte_rate.ok_or_else(|| anyhow!("fmt chunk not found"))?;
+                if byte_rate == 0 {
+                    return Err(anyhow!("WAV byte rate is zero"));
+                }
+                let size = match (is_64 && sz32 == u32::MAX, data_size64) {
+                    (true, Some(size)) => size,
+                    (true, None) => return Err(anyhow!("RF64 file without ds64 chunk")),
+                    (false, _) => sz32 as u64,
+                };
+                let size = size.min(file_len - body_start);
+                return Ok(size as f64 / byte_rate as f64);
+            }
+            _ => {}
+        }
+        off = body_start + sz32 as u64 + (sz32 % 2) as u64; // chunks are word-aligned
+    }
+    Err(anyhow!("data chunk not found"))
+}
+
+/// Running measurements over a stream of samples that may arrive in pieces of any size:
+/// the share of near-silent samples and the RMS level of each `frame_ms` window.
+struct PcmMeter {
+    /// None for sample formats that can't be decoded; nothing is measured then.
+    decode: Option<fn(&[u8]) -> f32>,
+    sample_bytes: usize,
+    window_bytes: usize,
+    /// ~ -40 dBFS for integer PCM, 0.5% FS for float
+    threshold: f32,
+    /// Bytes of a sample split across two pieces.
+    carry: Vec<u8>,
+    silent: u64,
+    samples: u64,
+    window_sum: f32,
+    window_samples: usize,
+    window_filled: usize,
+    envelope: Vec<f32>,
+}
+
+impl PcmMeter {
+    fn new(fmt: &WavFmt, frame_ms: u32) -> Self {
+        let sample_bytes = (fmt.bits_per_sample as usize).div_ceil(8).max(1);
+        let frames_per_window = (fmt.sample_rate as u64 * frame_ms as u64 / 1000).max(1) as usize;
+        Self {
+            decode: sample_decoder(fmt).ok(),
+            sample_bytes,
+            window_bytes: frames_per_window * (fmt.block_align as usize).max(sample_bytes),
+            threshold: if fmt.sample_format() == WAVE_FORMAT_IEEE_FLOAT {
+                0.005
+            } else {
+                0.01
+            },
+            carry: Vec::new(),
+            silent: 0,
+            samples: 0,
+            window_sum: 0.0,
+            window_samples: 0,
+            window_filled: 0,
+            envelope: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        let Some(decode) = self.decode else {
+            return;
+        };
+        if !self.carry.is_empty() {
+            let take = (self.sample_bytes - self.carry.len()).min(data.len());
+            self.carry.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.carry.len() < self.sample_bytes {
+                return;
+            }
+            let sample = std::mem::take(&mut self.carry);
+            self.add(decode(&sample));
+        }
+        let mut samples = data.chunks_exact(self.sample_bytes);
+        for sample in &mut samples {
+            self.add(decode(sample));
+        }
+        self.carry.extend_from_slice(samples.remainder());
+    }
+
+    fn add(&mut self, v: f32) {
+        self.samples += 1;
+        if v.abs() <= self.threshold {
+            self.silent += 1;
+        }
+        self.window_sum += v * v;
+        self.window_samples += 1;
+        self.window_filled += self.sample_bytes;
+        if self.window_filled >= self.window_bytes {
+            self.close_window();
+        }
     }
 
-    // data chunk
-    out.extend_from_slice(b"data");
-    out.extend_from_slice(&(data_len as u32).to_le_bytes());
-    Ok(())
+    fn close_window(&mut self) {
+        let n = self.window_samples;
+        self.envelope.push(if n == 0 {
+            0.0
+        } else {
+            (self.window_sum / n as f32).sqrt()
+        });
+        self.window_sum = 0.0;
+        self.window_samples = 0;
+        self.window_filled = 0;
+    }
+
+    /// Share of near-silent samples; 1.0 for no samples, None if the format can't be decoded.
+    fn silence_ratio(&self) -> Option<f32> {
+        self.decode?;
+        Some(if self.samples == 0 {
+            1.0
+        } else {
+            self.silent as f32 / self.samples as f32
+        })
+    }
+
+    /// Window levels, including a final partial window.
+    fn envelope(mut self) -> Option<Vec<f32>> {
+        self.decode?;
+        if self.window_filled > 0 {
+            self.close_window();
+        }
+        Some(self.envelope)
+    }
+}
+
+/// `LIST/INFO` chunk holding an `INAM` (title) entry.
+fn wav_title_chunk(title: &str) -> Vec<u8> {
+    let mut inam = title.as_bytes().to_vec();
+    inam.push(0);
+    let inam_len = inam.len() as u32;
+    if inam.len() % 2 == 1 {
+        inam.push(0);
+    }
+    let list_len = 4 + 8 + inam.len() as u32;
+
+    let mut chunk = Vec::with_capacity(8 + list_len as usize);
+    chunk.extend_from_slice(b"LIST");
+    chunk.extend_from_slice(&list_len.to_le_bytes());
+    chunk.extend_from_slice(b"INFO");
+    chunk.extend_from_slice(b"INAM");
+    chunk.extend_from_slice(&inam_len.to_le_bytes());
+    chunk.extend_from_slice(&inam);
+    chunk
+}
+
+/// ID3v2.4 tag with a single `TIT2` (title) frame.
+fn mp3_title_tag(title: &str) -> Vec<u8> {
+    // TIT2 frame: encoding byte (3 = UTF-8) followed by the text
+    let mut frame_body = vec![3u8];
+    frame_body.extend_from_slice(title.as_bytes());
+
+    let mut tag = Vec::with_capacity(20 + frame_body.len());
+    tag.extend_from_slice(b"ID3");
+    tag.extend_from_slice(&[4, 0, 0]); // v2.4.0, no flags
+    tag.extend_from_slice(&synchsafe(10 + frame_body.len() as u32));
+    tag.extend_from_slice(b"TIT2");
+    tag.extend_from_slice(&synchsafe(frame_body.len() as u32));
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend_from_slice(&frame_body);
+    tag
+}
+
+fn synchsafe(v: u32) -> [u8; 4] {
+    [
+        ((v >> 21) & 0x7f) as u8,
+        ((v >> 14) & 0x7f) as u8,
+        ((v >> 7) & 0x7f) as u8,
+        (v & 0x7f) as u8,
+    ]
 }
 
 pub fn is_raw_linear_pcm(mime: &str) -> bool {
     let m = mime.to_ascii_lowercase();
-    (m.contains("linear16") || m.contains("pcm")) && !m.contains("wav")
+    (m.contains("linear16") || m.contains("pcm") || parse_sample_bits(mime).is_some())
+        && !m.contains("wav")
 }
 
 pub fn parse_sample_rate(mime: &str) -> Option<u32> {
     // examples: "audio/pcm;rate=24000" or "audio/linear16; sample_rate=16000"
+    mime_number_param(mime, &["rate=", "samplerate=", "sample_rate="])
+}
+
+/// Channel count of raw PCM, e.g. `audio/L16;rate=24000;channels=2`.
+pub fn parse_channels(mime: &str) -> Option<u32> {
+    mime_number_param(mime, &["channels=", "channel="])
+}
+
+/// Bits per sample of raw PCM from the subtype: `audio/L8`, `L16`, `L24`, `L32` (RFC 3190)
+/// or `audio/linear16`.
+pub fn parse_sample_bits(mime: &str) -> Option<u16> {
+    let lower = mime.to_ascii_lowercase();
+    let subtype = lower.split(';').next()?.split('/').nth(1)?.trim();
+    let digits = subtype
+        .strip_prefix("linear")
+        .or_else(|| subtype.strip_prefix('l'))?;
+    digits.parse().ok()
+}
+
+/// First number following one of `keys` in a MIME type's parameters.
+fn mime_number_param(mime: &str, keys: &[&str]) -> Option<u32> {
     let lower = mime.to_ascii_lowercase();
-    for key in ["rate=", "samplerate=", "sample_rate="] {
+    for key in keys {
         if let Some(pos) = lower.find(key) {
             let tail = &lower[pos + key.len()..];
             let mut num = String::new();
@@ -214,106 +787,221 @@ pub fn parse_sample_rate(mime: &str) -> Option<u32> {
     None
 }
 
-pub fn wrap_pcm_to_wav(
-    pcm: &[u8],
-    sample_rate: u32,
-    channels: u16,
-    bits_per_sample: u16,
-) -> Result<Vec<u8>> {
-    let block_align = channels * (bits_per_sample / 8);
-    let byte_rate = sample_rate * block_align as u32;
-    let fmt = WavFmt {
-        audio_format: 1, // PCM
+/// WAV format for integer PCM. More than 16 bits or more than two channels get a
+/// WAVE_FORMAT_EXTENSIBLE header, as the format requires.
+fn pcm_wav_fmt(sample_rate: u32, channels: u32, bits_per_sample: u16) -> Result<WavFmt> {
+    if ![8, 16, 24, 32].contains(&bits_per_sample) {
+        return Err(anyhow!(
+            "unsupported PCM sample size: {} bits",
+            bits_per_sample
+        ));
+    }
+    if channels == 0 || sample_rate == 0 {
+        return Err(anyhow!(
+            "invalid PCM format: {} channel(s) at {} Hz",
+            channels,
+            sample_rate
+        ));
+    }
+    // Channel count, frame size and byte rate are 16, 16 and 32-bit fields in the WAV header
+    let too_many = || anyhow!("too many PCM channels for WAV: {}", channels);
+    let channels = u16::try_from(channels).map_err(|_| too_many())?;
+    let block_align = channels
+        .checked_mul(bits_per_sample / 8)
+        .ok_or_else(too_many)?;
+    let byte_rate = sample_rate.checked_mul(block_align as u32).ok_or_else(|| {
+        anyhow!(
+            "PCM byte rate too high for WAV: {} Hz x {} bytes per frame",
+            sample_rate,
+            block_align
+        )
+    })?;
+    let (audio_format, extension) = if bits_per_sample > 16 || channels > 2 {
+        // Front left/right (and centre for mono); no speaker positions beyond stereo
+        let channel_mask: u32 = match channels {
+            1 => 0x4,
+            2 => 0x3,
+            _ => 0,
+        };
+        let mut ext = Vec::with_capacity(24);
+        ext.extend_from_slice(&22u16.to_le_bytes()); // cbSize
+        ext.extend_from_slice(&bits_per_sample.to_le_bytes()); // valid bits per sample
+        ext.extend_from_slice(&channel_mask.to_le_bytes());
+        ext.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        ext.extend_from_slice(&KSDATAFORMAT_SUBTYPE_TAIL);
+        (WAVE_FORMAT_EXTENSIBLE, ext)
+    } else {
+        (WAVE_FORMAT_PCM, Vec::new())
+    };
+    Ok(WavFmt {
+        audio_format,
         num_channels: channels,
         sample_rate,
         byte_rate,
         block_align,
         bits_per_sample,
-    };
-    let mut out = Vec::with_capacity(44 + pcm.len());
-    write_wav_header(&mut out, &fmt, 16, pcm.len())?;
-    out.extend_from_slice(pcm);
-    Ok(out)
+        extension,
+    })
 }
 
-/// Estimate the ratio of samples that are effectively silent for WAV data.
-/// Returns a value in [0.0, 1.0] where 1.0 means all samples are near-zero.
-/// Supports PCM 16-bit integer and 32-bit float WAV.
-pub fn estimate_wav_silence_ratio(bytes: &[u8]) -> Result<f32> {
-    let (fmt, _fmt_size) = parse_wav_fmt(bytes)?;
-    let data = parse_wav_data(bytes)?;
-
-    if data.is_empty() {
-        return Ok(1.0);
-    }
-
-    let bps = fmt.bits_per_sample;
-
-    match (fmt.audio_format, bps) {
-        // PCM 16-bit
-        (1, 16) => {
-            let sample_count = data.len() / 2; // 2 bytes per sample (per channel)
-            if sample_count == 0 { return Ok(1.0); }
-            let mut silent = 0usize;
-            let mut total = 0usize;
-            let threshold: i32 = (32767f32 * 0.01) as i32; // ~ -40 dBFS
-            let mut i = 0;
-            while i + 1 < data.len() {
-                let s = i16::from_le_bytes([data[i], data[i+1]]) as i32;
-                if s.abs() as i32 <= threshold { silent += 1; }
-                total += 1;
-                i += 2;
-            }
-            Ok((silent as f32) / (total as f32))
-        }
-        // IEEE float 32-bit
-        (3, 32) => {
-            let sample_count = data.len() / 4;
-            if sample_count == 0 { return Ok(1.0); }
-            let mut silent = 0usize;
-            let mut total = 0usize;
-            let mut i = 0;
-            let threshold = 0.005f32; // 0.5% FS
-            while i + 3 < data.len() {
-                let v = f32::from_le_bytes([
-                    data[i], data[i+1], data[i+2], data[i+3]
-                ]);
-                if v.abs() <= threshold { silent += 1; }
-                total += 1;
-                i += 4;
-            }
-            Ok((silent as f32) / (total as f32))
-        }
-        // Unsupported formats — fall back to a basic zero-byte heuristic per frame
-        _ => {
-            // Heuristic: consider frames (block_align) and count near-zero frames
-            let ba = fmt.block_align.max(1) as usize;
-            let mut silent = 0usize;
-            let mut total = 0usize;
-            let mut i = 0usize;
-            while i + ba <= data.len() {
-                let frame = &data[i..i+ba];
-                // If most bytes in frame are 0x00 or 0x80 (common silence centers), mark silent
-                let zeros = frame.iter().filter(|b| **b == 0x00 || **b == 0x80).count();
-                if zeros * 2 >= frame.len() { // >= 50% zero-ish bytes
-                    silent += 1;
-                }
-                total += 1;
-                i += ba;
-            }
-            if total == 0 { return Ok(1.0); }
-            Ok((silent as f32) / (total as f32))
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audio-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
     }
-}
 
-/// Convenience helper: if `mime` indicates WAV, estimate silence ratio.
-/// Returns None when mime is not WAV or parsing fails.
-pub fn try_silence_ratio_from_mime(bytes: &[u8], mime: &str) -> Option<f32> {
-    let lower = mime.to_ascii_lowercase();
-    if lower.contains("wav") || lower.contains("x-wav") {
-        estimate_wav_silence_ratio(bytes).ok()
-    } else {
-        None
+    fn samples(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_pcm_mime_parameters() {
+        let mime = "audio/L16;codec=pcm;rate=24000;channels=2";
+        assert!(is_raw_linear_pcm(mime));
+        assert_eq!(parse_sample_rate(mime), Some(24000));
+        assert_eq!(parse_channels(mime), Some(2));
+        assert_eq!(parse_sample_bits(mime), Some(16));
+        assert_eq!(
+            parse_sample_bits("audio/linear16; sample_rate=16000"),
+            Some(16)
+        );
+        assert_eq!(
+            parse_sample_rate("audio/linear16; sample_rate=16000"),
+            Some(16000)
+        );
+        assert!(!is_raw_linear_pcm("audio/wav"));
+        assert!(!is_raw_linear_pcm("audio/mpeg"));
+    }
+
+    #[test]
+    fn pcm_format_rejects_out_of_range_headers() {
+        let fmt = pcm_wav_fmt(24000, 1, 16).unwrap();
+        assert_eq!((fmt.block_align, fmt.byte_rate), (2, 48000));
+        assert_eq!(fmt.audio_format, WAVE_FORMAT_PCM);
+        let fmt = pcm_wav_fmt(48000, 2, 24).unwrap();
+        assert_eq!(fmt.audio_format, WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(fmt.sample_format(), WAVE_FORMAT_PCM);
+        assert!(pcm_wav_fmt(24000, 0, 16).is_err());
+        assert!(pcm_wav_fmt(0, 1, 16).is_err());
+        assert!(pcm_wav_fmt(24000, 1, 12).is_err());
+        assert!(pcm_wav_fmt(24000, 70_000, 16).is_err());
+        assert!(pcm_wav_fmt(24000, 40_000, 16).is_err()); // block align overflows u16
+        assert!(pcm_wav_fmt(u32::MAX, 2, 16).is_err()); // byte rate overflows u32
+    }
+
+    #[test]
+    fn writer_merges_pcm_fragments_and_discards_parts() {
+        let dir = temp_dir("pcm");
+        let tone = samples(&[1000; 2400]); // 0.1 s at 24 kHz
+        let mut w = ChapterAudioWriter::create(
+            &dir,
+            "ch01",
+            &tone[..7],
+            "audio/L16;codec=pcm;rate=24000",
+            Some("Chapter"),
+        )
+        .unwrap();
+        assert!(w.is_wav());
+        assert_eq!(w.mime(), "audio/wav");
+        w.write_part(&tone[7..1001]).unwrap();
+        w.write_part(&tone[1001..]).unwrap();
+        let stats = w.end_part();
+        assert_eq!(stats.bytes, 4800);
+        assert_eq!(stats.duration, Some(0.1));
+        assert_eq!(stats.silence_ratio, Some(0.0));
+
+        w.begin_part();
+        w.write_part(&samples(&[0; 500])).unwrap();
+        w.discard_part().unwrap();
+        assert_eq!(w.parts(), 1);
+        assert_eq!(w.append_silence(50).unwrap(), 0.05);
+        assert_eq!(w.parts(), 2);
+
+        let (path, size) = w.finish().unwrap();
+        assert_eq!(path, dir.join("ch01.wav"));
+        assert!(!dir.join("ch01.wav.part").exists());
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len() as u64, size);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+            bytes.len() - 8
+        );
+        let data = parse_wav_data(&bytes).unwrap();
+        assert_eq!(data.len(), 4800 + 2400);
+        assert_eq!(&data[..4800], &tone[..]);
+        assert!(find_wav_chunk(&bytes, b"LIST").unwrap().is_some());
+        assert!((wav_duration_from_path(&path).unwrap() - 0.15).abs() < 1e-9);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writer_switches_to_rf64_beyond_4_gib() {
+        let dir = temp_dir("rf64");
+        let mut w = ChapterAudioWriter::create(
+            &dir,
+            "big",
+            &samples(&[7; 24]),
+            "audio/L16;rate=24000",
+            None,
+        )
+        .unwrap();
+        w.end_part();
+        // Pretend the data outgrew 32-bit sizes without writing gigabytes
+        w.data_len = 5 << 30;
+        let (path, _) = w.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        assert_eq!(&bytes[0..4], b"RF64");
+        assert_eq!(u32_at(4), u32::MAX);
+        assert_eq!(&bytes[12..16], b"ds64");
+        assert_eq!(u32_at(16), DS64_BODY_LEN);
+        assert_eq!(u64_at(20), bytes.len() as u64 - 8);
+        assert_eq!(u64_at(28), 5 << 30);
+        assert_eq!(u64_at(36), (5 << 30) / 2);
+        assert_eq!(&bytes[72..76], b"data");
+        assert_eq!(u32_at(76), u32::MAX);
+        // The data chunk claims more than the file holds; the duration stops at its end
+        assert_eq!(wav_duration_from_path(&path).unwrap(), 48.0 / 48000.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finds_chunks_sized_by_ds64() {
+        let fmt = pcm_wav_fmt(24000, 1, 16).unwrap();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RF64");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(&ds64_chunk(&fmt, 80, 4));
+        write_fmt_chunk(&mut bytes, &fmt);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(parse_wav_fmt(&bytes).unwrap(), fmt);
+        assert_eq!(parse_wav_data(&bytes).unwrap(), [1, 2, 3, 4]);
+
+        bytes[12..16].copy_from_slice(b"JUNK");
+        assert!(find_wav_chunk(&bytes, b"data").is_err());
+        assert!(find_wav_chunk(b"RIFF\0\0\0\0AVI ", b"data").is_err());
+    }
+
+    #[test]
+    fn meter_measures_samples_split_across_pieces() {
+        let fmt = pcm_wav_fmt(1000, 1, 16).unwrap();
+        let mut meter = PcmMeter::new(&fmt, 10); // 10 samples per window
+        let mut pcm = samples(&[0; 10]);
+        pcm.extend(samples(&[16384; 10]));
+        pcm.extend(samples(&[0; 5]));
+        for piece in pcm.chunks(3) {
+            meter.push(piece);
+        }
+        assert_eq!(meter.silence_ratio(), Some(15.0 / 25.0));
+        assert_eq!(meter.envelope(), Some(vec![0.0, 0.5, 0.0]));
     }
 }
@@ -0,0 +1,131 @@
+use regex::Regex;
+
+/// Labels recognized at the start of a blockquote, as `Note:`, `**Note:**` or `[!NOTE]`.
+const CALLOUT_LABELS: &[&str] = &["note", "warning", "important", "tip", "caution", "hint"];
+
+/// Rewrite blockquotes into narrated asides with a spoken lead-in and end cue.
+///
+/// `> Note: text` becomes "Note." / "text" / "End of note.", GitHub-style `> [!WARNING]` callouts
+/// likewise, and a blockquote opening with a heading (the Rust Book's "The Stack and the Heap")
+/// becomes "Aside: The Stack and the Heap." ... "End of aside.". Other blockquotes are read as
+/// quotes. The `>` markers are removed, so headings inside a blockquote no longer start a
+/// section of their own.
+pub fn narrate_callouts(input: &str) -> String {
+    let re_quote = Regex::new(r"^ {0,3}>").unwrap();
+    // Lines that start a new block and so can't continue a quote lazily: fences, headings,
+    // list items, HTML blocks and thematic breaks
+    let re_block_start =
+        Regex::new(r"^ {0,3}(```|~~~|#{1,6}(\s|$)|[-*+]\s|\d{1,9}[.)]\s|<|([-*_]\s*){3,}$)")
+            .unwrap();
+    let mut out: Vec<String> = Vec::new();
+    let mut quote: Vec<String> = Vec::new();
+    let mut in_fence = false;
+
+    for line in input.lines() {
+        if !quote.is_empty() && !re_quote.is_match(line) && re_block_start.is_match(line) {
+            out.extend(narrate_quote(&quote));
+            quote.clear();
+        }
+        if line.trim_start().starts_with("```") && quote.is_empty() {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            out.push(line.to_string());
+            continue;
+        }
+        if re_quote.is_match(line) {
+            quote.push(strip_quote_markers(line));
+            continue;
+        }
+        // Lazy continuation: any other non-blank line right after a quoted line belongs to
+        // the quote
+        if !quote.is_empty() && !line.trim().is_empty() {
+            quote.push(line.trim().to_string());
+            continue;
+        }
+        if !quote.is_empty() {
+            out.extend(narrate_quote(&quote));
+            quote.clear();
+        }
+        out.push(line.to_string());
+    }
+    if !quote.is_empty() {
+        out.extend(narrate_quote(&quote));
+    }
+    out.join("\n")
+}
+
+fn strip_quote_markers(line: &str) -> String {
+    let mut l = line.trim_start();
+    while let Some(rest) = l.strip_prefix('>') {
+        l = rest.trim_start();
+    }
+    l.to_string()
+}
+
+/// Spoken form of one blockquote: lead-in line, body paragraphs, end cue.
+fn narrate_quote(lines: &[String]) -> Vec<String> {
+    let re_label =
+        Regex::new(r"(?i)^(?:\*\*|__|\*|_)?(\w+)(?:\*\*|__|\*|_)?:(?:\*\*|__|\*|_)?\s*(.*)$")
+            .unwrap();
+    let re_alert = Regex::new(r"^\[!(\w+)\]\s*(.*)$").unwrap();
+    let re_heading = Regex::new(r"^#{1,6}\s+(.*?)\s*#*\s*$").unwrap();
+
+    let mut body: Vec<String> = lines.to_vec();
+    while body.first().is_some_and(|l| l.trim().is_empty()) {
+        body.remove(0);
+    }
+    while body.last().is_some_and(|l| l.trim().is_empty()) {
+        body.pop();
+    }
+    let Some(first) = body.first().cloned() else {
+        return Vec::new();
+    };
+
+    let (kind, lead_in) = if let Some(caps) = re_alert
+        .captures(&first)
+        .or_else(|| re_label.captures(&first))
+        .filter(|c| CALLOUT_LABELS.contains(&c[1].to_lowercase().as_str()))
+    {
+        let kind = caps[1].to_lowercase();
+        let rest = caps[2].trim().to_string();
+        if rest.is_empty() {
+            body.remove(0);
+        } else {
+            body[0] = rest;
+        }
+        (kind.clone(), format!("{}.", capitalize(&kind)))
+    } else if let Some(caps) = re_heading.captures(&first) {
+        let title = caps[1].trim().to_string();
+        body.remove(0);
+        ("aside".to_string(), format!("Aside: {}.", title))
+    } else {
+        ("quote".to_string(), "Quote.".to_string())
+    };
+
+    // Headings inside the quote are read as plain lines so they don't split the section
+    let body = body
+        .into_iter()
+        .map(|l| match re_heading.captures(&l) {
+            Some(caps) => format!("{}.", caps[1].trim()),
+            None => l,
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = Vec::with_capacity(body.len() + 4);
+    out.push(lead_in);
+    out.push(String::new());
+    out.extend(body);
+    out.push(String::new());
+    out.push(format!("End of {}.", kind));
+    out.push(String::new());
+    out
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
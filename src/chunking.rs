@@ -0,0 +1,469 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Abbreviations that end in a period without ending the sentence (compared lowercase).
+const ABBREVIATIONS: &[&str] = &[
+    "e.g.", "i.e.", "etc.", "vs.", "cf.", "approx.", "al.", "mr.", "mrs.", "ms.", "dr.", "st.",
+    "fig.", "no.", "vol.", "ch.", "sec.", "ed.",
+];
+
+/// Smallest chunk, as a fraction of the balanced target size, worth cutting off.
+const MIN_FILL: f64 = 0.6;
+
+/// Characters per token assumed when estimating token counts (on the safe side for English).
+const CHARS_PER_TOKEN: usize = 3;
+
+/// Unit a TTS provider counts its input in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Measure {
+    /// Unicode scalar values.
+    Chars,
+    /// UTF-8 bytes.
+    Bytes,
+    /// Model tokens, estimated from the character count.
+    Tokens,
+}
+
+impl Measure {
+    /// Size of `text` in this unit.
+    pub fn of(self, text: &str) -> usize {
+        match self {
+            Measure::Chars => text.chars().count(),
+            Measure::Bytes => text.len(),
+            Measure::Tokens => text.chars().count().div_ceil(CHARS_PER_TOKEN),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Measure::Chars => "chars",
+            Measure::Bytes => "bytes",
+            Measure::Tokens => "tokens",
+        }
+    }
+}
+
+/// The most text one TTS request may carry, in the provider's unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub measure: Measure,
+    pub max: usize,
+}
+
+/// One TTS request's worth of narration, tied back to the part of the chapter it came from.
+#[derive(Clone, Debug, Serialize)]
+pub struct Chunk {
+    /// Source heading of the section the text belongs to (None before the first heading).
+    pub heading: Option<String>,
+    /// First and last line (1-based, inclusive) of that section in the chapter's Markdown,
+    /// when it could be located.
+    pub source_lines: Option<(usize, usize)>,
+    /// Silence to insert before this chunk's audio.
+    pub pause_before_ms: u32,
+    /// Text sent to TTS.
+    pub text: String,
+    /// The same text as written, before pronunciation respellings and number expansion.
+    /// Captions and the timing map use this form.
+    #[serde(skip)]
+    pub written: String,
+}
+
+/// Split section-sized chunks, whose `text` is still in its written form, into pieces that fit `budget`
+/// once `speak` (pronunciation and number rewriting) has been applied, as in
+/// [`split_into_chunks`]. Each piece keeps both forms. Pieces never cross a section
+/// boundary; each keeps its section's heading and line range, and only the first piece of a
+/// section keeps the section's leading pause.
+pub fn chunk_sections(
+    sections: Vec<Chunk>,
+    budget: Budget,
+    speak: impl Fn(&str) -> String,
+) -> Vec<Chunk> {
+    let mut out = Vec::new();
+    for section in sections {
+        for (k, (text, written)) in split_spoken(&section.text, budget, &speak)
+            .into_iter()
+            .enumerate()
+        {
+            out.push(Chunk {
+                heading: section.heading.clone(),
+                source_lines: section.source_lines,
+                pause_before_ms: if k == 0 { section.pause_before_ms } else { 0 },
+                text,
+                written,
+            });
+        }
+    }
+    out
+}
+
+/// Write the chunk list as JSON, so each part of the audio can be traced to its section.
+pub fn write_chunk_map(path: &Path, chunks: &[Chunk]) -> Result<()> {
+    let json = serde_json::to_string_pretty(chunks).context("failed to serialize chunk map")?;
+    fs::write(path, json).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// How good a place between two pieces of text is for ending a chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Break {
+    /// Between graphemes of an over-long token; only used when nothing else fits.
+    Grapheme,
+    /// At whitespace inside an over-long sentence.
+    Word,
+    /// After a sentence.
+    Sentence,
+    /// After a line (a paragraph in sanitized text, an element run in SSML).
+    Line,
+    /// At a blank line.
+    Paragraph,
+}
+
+/// Split text into chunks that each fit `budget` for TTS.
+///
+/// Breaks are preferred at blank lines, then line ends, then sentence ends (Unicode sentence
+/// segmentation, not splitting after abbreviations like "e.g." or inside `std::io.Read`), then
+/// whitespace. Text is only cut inside a word when a single word exceeds the limit. Chunk sizes
+/// are balanced so the last chunk is not a tiny fragment.
+pub fn split_into_chunks(input: &str, budget: Budget) -> Vec<String> {
+    split_spoken(input, budget, &|s: &str| s.to_string())
+        .into_iter()
+        .map(|(text, _)| text)
+        .collect()
+}
+
+/// [`split_into_chunks`] for text that is rewritten by `speak` before it is sent: the input
+/// is cut in its written form, sizes are measured on the spoken form, and each chunk is
+/// returned as `(spoken, written)`. `speak` is applied per sentence (or smaller piece).
+fn split_spoken(
+    input: &str,
+    budget: Budget,
+    speak: &impl Fn(&str) -> String,
+) -> Vec<(String, String)> {
+    let max = budget.max.max(1);
+    let len = |s: &str| budget.measure.of(s);
+    let pieces = pieces(input, max, &len, speak);
+
+    let mut chunks = Vec::new();
+    let mut start = 0usize;
+    while start < pieces.len() {
+        let remaining: usize = pieces[start..].iter().map(|p| len(&p.spoken)).sum();
+        if remaining <= max {
+            push_chunk(&mut chunks, &pieces[start..]);
+            break;
+        }
+
+        // Aim for equal-sized chunks over what is left, and leave enough for the rest
+        let n = remaining.div_ceil(max);
+        let target = remaining.div_ceil(n);
+        let min_len = (target as f64 * MIN_FILL) as usize;
+        let lo = min_len.max(remaining.saturating_sub((n - 1) * max));
+        let hi = max.min(remaining.saturating_sub(min_len));
+
+        // Candidate ends: (index after the piece, chunk length, break kind)
+        let mut candidates = Vec::new();
+        let mut size = 0usize;
+        for (i, piece) in pieces.iter().enumerate().skip(start) {
+            size += len(&piece.spoken);
+            if size > max {
+                break;
+            }
+            candidates.push((i + 1, size, piece.kind));
+        }
+
+        let in_window = candidates
+            .iter()
+            .filter(|(_, size, _)| (lo..=hi).contains(size))
+            .max_by_key(|(_, size, kind)| (*kind, usize::MAX - size.abs_diff(target)));
+        let end = match in_window.or_else(|| candidates.last()) {
+            Some((end, _, _)) => *end,
+            // A single piece can't exceed the limit, but never loop forever
+            None => start + 1,
+        };
+        push_chunk(&mut chunks, &pieces[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+fn push_chunk(chunks: &mut Vec<(String, String)>, pieces: &[Piece]) {
+    let spoken: String = pieces.iter().map(|p| p.spoken.as_str()).collect();
+    let written: String = pieces.iter().map(|p| p.written.as_str()).collect();
+    if !spoken.trim().is_empty() {
+        chunks.push((spoken.trim().to_string(), written.trim().to_string()));
+    }
+}
+
+/// A run of input text in its written and spoken forms, with the kind of break after it.
+struct Piece {
+    written: String,
+    spoken: String,
+    kind: Break,
+}
+
+impl Piece {
+    fn new(written: String, speak: &impl Fn(&str) -> String, kind: Break) -> Self {
+        Self {
+            spoken: speak(&written),
+            written,
+            kind,
+        }
+    }
+}
+
+/// Cut the input into pieces whose spoken form is no longer than `max`, each tagged with the
+/// kind of break that follows it. Concatenating the pieces gives back the input.
+fn pieces(
+    input: &str,
+    max: usize,
+    len: &impl Fn(&str) -> usize,
+    speak: &impl Fn(&str) -> String,
+) -> Vec<Piece> {
+    let mut out = Vec::new();
+    let lines: Vec<&str> = input.split_inclusive('\n').collect();
+    for (li, line) in lines.iter().enumerate() {
+        let next_blank = lines.get(li + 1).is_some_and(|l| l.trim().is_empty());
+        let line_break = if next_blank || line.trim().is_empty() {
+            Break::Paragraph
+        } else {
+            Break::Line
+        };
+
+        let sentences = sentences(line);
+        let last = sentences.len().saturating_sub(1);
+        for (si, sentence) in sentences.into_iter().enumerate() {
+            let kind = if si == last {
+                line_break
+            } else {
+                Break::Sentence
+            };
+            let piece = Piece::new(sentence, speak, kind);
+            if len(&piece.spoken) <= max {
+                out.push(piece);
+            } else {
+                let spoken_len = |s: &str| len(&speak(s));
+                split_long(&piece.written, max, &spoken_len, speak, kind, &mut out);
+            }
+        }
+    }
+    out
+}
+
+/// Sentences of `text` for display (captions, timing), trimmed and without empty entries.
+/// Uses the same segmentation and abbreviation handling as chunking; lines always end a sentence.
+pub fn split_sentences(text: &str) -> Vec<String> {
+    text.lines()
+        .flat_map(sentences)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Unicode sentences of one line, re-joined where the boundary follows an abbreviation or
+/// is not followed by whitespace (`std::io.Read`, `Cargo.toml`).
+fn sentences(line: &str) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut joining = false;
+    let mut bounds = line.split_sentence_bounds().peekable();
+    while let Some(s) = bounds.next() {
+        match out.last_mut() {
+            Some(prev) if joining => prev.push_str(s),
+            _ => out.push(s.to_string()),
+        }
+        let current = out.last().map(String::as_str).unwrap_or("");
+        joining = !current.ends_with(char::is_whitespace)
+            || ends_with_abbreviation(current, bounds.peek().copied());
+    }
+    out
+}
+
+fn ends_with_abbreviation(sentence: &str, next: Option<&str>) -> bool {
+    let Some(word) = sentence.split_whitespace().last() else {
+        return false;
+    };
+    let word = word.trim_start_matches(['(', '"', '\'', '“', '‘']);
+    if ABBREVIATIONS.contains(&word.to_lowercase().as_str()) {
+        return true;
+    }
+    // Initials ("J. Smith") only when followed by a capitalised name; "I." and a lone
+    // "a." end sentences as usual
+    let mut chars = word.chars();
+    let is_initial = matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(c), Some('.'), None) if c.is_uppercase() && c != 'I'
+    );
+    is_initial && next.is_some_and(|n| n.trim_start().starts_with(char::is_uppercase))
+}
+
+/// Split an over-long sentence at whitespace, falling back to grapheme clusters for a single
+/// word longer than `max`. `len` measures the spoken form. The last piece keeps the
+/// sentence's own break kind.
+fn split_long(
+    sentence: &str,
+    max: usize,
+    len: &impl Fn(&str) -> usize,
+    speak: &impl Fn(&str) -> String,
+    kind: Break,
+    out: &mut Vec<Piece>,
+) {
+    let mut words: Vec<String> = Vec::new();
+    for segment in sentence.split_word_bounds() {
+        let after_space = words
+            .last()
+            .is_some_and(|w| w.ends_with(char::is_whitespace));
+        match words.last_mut() {
+            Some(w) if !after_space || segment.trim().is_empty() => w.push_str(segment),
+            _ => words.push(segment.to_string()),
+        }
+    }
+
+    let count = words.len();
+    for (i, word) in words.into_iter().enumerate() {
+        let word_kind = if i + 1 == count { kind } else { Break::Word };
+        if len(&word) <= max {
+            out.push(Piece::new(word, speak, word_kind));
+            continue;
+        }
+        let mut parts = vec![String::new()];
+        for g in word.graphemes(true) {
+            let current = parts.last_mut().expect("parts is never empty");
+            if !current.is_empty() && len(&format!("{}{}", current, g)) > max {
+                parts.push(g.to_string());
+            } else {
+                current.push_str(g);
+            }
+        }
+        let parts_count = parts.len();
+        for (k, part) in parts.into_iter().enumerate() {
+            let part_kind = if k + 1 == parts_count {
+                word_kind
+            } else {
+                Break::Grapheme
+            };
+            out.push(Piece::new(part, speak, part_kind));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(max: usize) -> Budget {
+        Budget {
+            measure: Measure::Chars,
+            max,
+        }
+    }
+
+    #[test]
+    fn sentences_skip_abbreviations_initials_and_dotted_tokens() {
+        assert_eq!(
+            split_sentences("Use a tool, e.g. rustup. Then stop."),
+            ["Use a tool, e.g. rustup.", "Then stop."]
+        );
+        assert_eq!(
+            split_sentences("Written by J. Smith. It works."),
+            ["Written by J. Smith.", "It works."]
+        );
+        assert_eq!(
+            split_sentences("Edit Cargo.toml and std::io.Read now. Done."),
+            ["Edit Cargo.toml and std::io.Read now.", "Done."]
+        );
+        // "I." always ends a sentence; an initial only continues into a capitalised name
+        assert_eq!(
+            split_sentences("So do I. Then go."),
+            ["So do I.", "Then go."]
+        );
+        // Lines always end a sentence
+        assert_eq!(split_sentences("One\nTwo"), ["One", "Two"]);
+    }
+
+    #[test]
+    fn over_long_words_are_cut_at_graphemes() {
+        let word = "é".repeat(25);
+        let chunks = split_into_chunks(&word, chars(10));
+        assert!(
+            chunks.iter().all(|c| c.chars().count() <= 10),
+            "{:?}",
+            chunks
+        );
+        assert_eq!(chunks.concat(), word);
+    }
+
+    #[test]
+    fn chunks_fit_the_budget_and_are_balanced() {
+        let text = "This sentence is exactly forty chars ok. ".repeat(13);
+        let chunks = split_into_chunks(&text, chars(300));
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 300));
+        // 13 sentences split 7/6 or 6/7, not 7 then a tiny remainder
+        let sizes: Vec<usize> = chunks.iter().map(|c| c.chars().count()).collect();
+        assert!(sizes.iter().all(|&s| s >= 200), "{:?}", sizes);
+    }
+
+    #[test]
+    fn paragraph_breaks_are_preferred() {
+        let text =
+            "First paragraph sentence one. Sentence two.\n\nSecond paragraph here. And more text.";
+        let chunks = split_into_chunks(text, chars(60));
+        assert_eq!(
+            chunks,
+            [
+                "First paragraph sentence one. Sentence two.",
+                "Second paragraph here. And more text."
+            ]
+        );
+    }
+
+    #[test]
+    fn budget_measures_tokens_and_bytes() {
+        assert_eq!(Measure::Chars.of("héllo"), 5);
+        assert_eq!(Measure::Bytes.of("héllo"), 6);
+        assert_eq!(Measure::Tokens.of("héllo"), 2);
+        let chunks = split_into_chunks(
+            &"abc ".repeat(100),
+            Budget {
+                measure: Measure::Tokens,
+                max: 20,
+            },
+        );
+        assert!(chunks.iter().all(|c| Measure::Tokens.of(c) <= 20));
+    }
+
+    #[test]
+    fn chunk_sections_measure_spoken_text_and_keep_written_text() {
+        let section = |text: &str, pause| Chunk {
+            heading: Some("Intro".to_string()),
+            source_lines: Some((1, 4)),
+            pause_before_ms: pause,
+            text: text.to_string(),
+            written: text.to_string(),
+        };
+        let sections = vec![
+            section("Version 1.0 is out. Version 2.0 is next.", 500),
+            section("Tail.", 0),
+        ];
+        // Spoken form is twice as long, so the first section no longer fits in one chunk
+        let chunks = chunk_sections(sections, chars(50), |w| {
+            w.replace("1.0", "one point zero one point zero")
+                .replace("2.0", "two point zero two point zero")
+        });
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].written, "Version 1.0 is out.");
+        assert_eq!(
+            chunks[0].text,
+            "Version one point zero one point zero is out."
+        );
+        assert_eq!(chunks[1].written, "Version 2.0 is next.");
+        assert_eq!(chunks[2].written, "Tail.");
+        assert_eq!(
+            chunks.iter().map(|c| c.pause_before_ms).collect::<Vec<_>>(),
+            [500, 0, 0]
+        );
+        assert!(chunks.iter().all(|c| c.text.chars().count() <= 50));
+        assert_eq!(chunks[1].heading.as_deref(), Some("Intro"));
+        assert_eq!(chunks[1].source_lines, Some((1, 4)));
+    }
+}
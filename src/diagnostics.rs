@@ -0,0 +1,65 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::Path;
+
+/// How the pipeline reacts to recoverable problems in a chapter (unreadable includes,
+/// missing anchors, failed summaries). Error text is never inserted into the narration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Abort the chapter on the first problem.
+    Strict,
+    /// Drop the offending content and record a warning in the chapter diagnostics.
+    #[default]
+    Warn,
+}
+
+impl Strictness {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" | "error" => Some(Strictness::Strict),
+            "warn" | "lenient" => Some(Strictness::Warn),
+            _ => None,
+        }
+    }
+}
+
+/// Per-chapter collection of warnings produced while preparing the narration.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    pub warnings: Vec<String>,
+}
+
+impl Diagnostics {
+    /// Record `msg` under the given strictness: returns an error in strict mode,
+    /// otherwise stores the warning and echoes it to stderr.
+    pub fn report(&mut self, strictness: Strictness, msg: String) -> Result<()> {
+        match strictness {
+            Strictness::Strict => Err(anyhow!(msg)),
+            Strictness::Warn => {
+                eprintln!("warn: {}", msg);
+                self.warnings.push(msg);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Write the warnings as a plain-text report, one per line.
+    pub fn write_report(&self, path: &Path, chapter: &Path) -> Result<()> {
+        let mut out = format!(
+            "Diagnostics for {} ({} warning(s))\n",
+            chapter.display(),
+            self.warnings.len()
+        );
+        for w in &self.warnings {
+            out.push_str("- ");
+            out.push_str(w);
+            out.push('\n');
+        }
+        fs::write(path, out)
+            .with_context(|| format!("failed to write diagnostics report {}", path.display()))
+    }
+}
@@ -0,0 +1,644 @@
+use anyhow::{Context, Result, anyhow};
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+use crate::align::{SentenceTiming, find_sentence};
+use crate::audio::wav_duration_from_path;
+use crate::diagnostics::{Diagnostics, Strictness};
+use crate::markdown::{expand_includes, extract_chapter_meta};
+use crate::ssml::escape_xml;
+use crate::summary::{BookMeta, Summary};
+
+/// Class reading systems put on the element being read aloud.
+const ACTIVE_CLASS: &str = "-epub-media-overlay-active";
+
+const STYLESHEET: &str = "body { font-family: serif; line-height: 1.5; }\n\
+pre { white-space: pre-wrap; font-size: 0.85em; }\n\
+blockquote { margin-left: 1.5em; font-style: italic; }\n\
+table { border-collapse: collapse; }\n\
+td, th { border: 1px solid #999; padding: 0.2em 0.4em; }\n\
+.-epub-media-overlay-active { background-color: #ffeb99; }\n";
+
+/// Audio formats bundled into the EPUB, in order of preference, with their media types.
+/// MP3 is an EPUB 3 core media type; WAV is bundled as a foreign resource that not every
+/// reading system plays.
+const AUDIO_TYPES: &[(&str, &str)] = &[("mp3", "audio/mpeg"), ("wav", "audio/wav")];
+
+/// One rendered chapter of the EPUB.
+struct Chapter {
+    title: String,
+    /// File stem shared by the Markdown, XHTML, SMIL and audio files.
+    stem: String,
+    xhtml: String,
+    overlay: Option<Overlay>,
+}
+
+/// Media overlay for a chapter: the bundled audio and the SMIL document pointing into it.
+struct Overlay {
+    audio: PathBuf,
+    media_type: &'static str,
+    smil: String,
+    duration: f64,
+}
+
+/// A rendered block element that can be highlighted during playback.
+struct Block {
+    id: String,
+    /// Markdown source of the block, used to find where it is read in the audio.
+    markdown: String,
+    heading: bool,
+}
+
+/// Build an EPUB 3 book from the chapters in `src_dir` and the audio in `audio_dir`.
+///
+/// Chapters are taken in `SUMMARY.md` order (or file name order without one) and rendered to
+/// XHTML with an `id` on every heading, paragraph, list item and table. A chapter with audio
+/// gets a SMIL media overlay: each block whose text can be found in
+/// `audio/<chapter>.timings.json` becomes a `<par>` clip running from its first sentence to
+/// where the next matched block starts, so reading systems highlight the text being spoken.
+/// Returns the number of chapters with audio.
+pub fn export_epub(src_dir: &Path, audio_dir: &Path, output: &Path) -> Result<usize> {
+    let meta = BookMeta::from_book(src_dir);
+    let mut chapters = Vec::new();
+    for (path, title) in chapter_list(src_dir)? {
+        chapters.push(render_chapter(&path, title, audio_dir)?);
+    }
+    if chapters.is_empty() {
+        return Err(anyhow!("no chapters found in {}", src_dir.display()));
+    }
+    write_epub(output, &meta, &chapters)?;
+    Ok(chapters.iter().filter(|c| c.overlay.is_some()).count())
+}
+
+/// Chapter files with their table-of-contents titles, in reading order.
+fn chapter_list(src_dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let summary_path = src_dir.join("SUMMARY.md");
+    if summary_path.exists() {
+        let summary = Summary::load(&summary_path)?;
+        return Ok(summary
+            .entries
+            .into_iter()
+            .filter_map(|e| {
+                let path = src_dir.join(e.path?);
+                path.exists().then_some((path, e.title))
+            })
+            .collect());
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(src_dir)
+        .with_context(|| format!("failed to read {}", src_dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "md"))
+        .collect();
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|p| {
+            let title = file_stem(&p);
+            (p, title)
+        })
+        .collect())
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("chapter")
+        .to_string()
+}
+
+fn render_chapter(path: &Path, toc_title: String, audio_dir: &Path) -> Result<Chapter> {
+    let raw =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let (markdown, meta) = extract_chapter_meta(&raw);
+    let mut diagnostics = Diagnostics::default();
+    let markdown = expand_includes(path, &markdown, Strictness::Warn, &mut diagnostics)?;
+    let stem = file_stem(path);
+    let title = meta.title.unwrap_or(toc_title);
+
+    let (body, blocks) = markdown_to_xhtml(&markdown);
+    let xhtml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n<head>\n<title>{}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_xml(&title),
+        body
+    );
+
+    let overlay = match AUDIO_TYPES
+        .iter()
+        .map(|(ext, media_type)| (audio_dir.join(format!("{}.{}", stem, ext)), *media_type))
+        .find(|(p, _)| p.exists())
+    {
+        Some((audio, media_type)) => {
+            let timings: Vec<SentenceTiming> =
+                fs::read_to_string(audio_dir.join(format!("{}.timings.json", stem)))
+                    .ok()
+                    .and_then(|t| serde_json::from_str(&t).ok())
+                    .unwrap_or_default();
+            if timings.is_empty() {
+                eprintln!(
+                    "warn: no timing map for {}; bundling audio without read-along",
+                    stem
+                );
+            }
+            let duration = audio_duration(&audio, &timings);
+            let audio_href = format!("audio/{}", file_name(&audio));
+            match smil_document(&stem, &audio_href, &blocks, &timings, duration) {
+                Some(smil) => Some(Overlay {
+                    audio,
+                    media_type,
+                    smil,
+                    duration,
+                }),
+                None => {
+                    eprintln!(
+                        "warn: {} has no text blocks to pair with audio; skipping its media overlay",
+                        stem
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    Ok(Chapter {
+        title,
+        stem,
+        xhtml,
+        overlay,
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Length of the chapter audio: read from the WAV header, or the end of the last timed sentence.
+fn audio_duration(audio: &Path, timings: &[SentenceTiming]) -> f64 {
+    let from_wav = if audio.extension().is_some_and(|e| e == "wav") {
+        wav_duration_from_path(audio).ok()
+    } else {
+        None
+    };
+    from_wav.unwrap_or_else(|| timings.last().map_or(0.0, |t| t.end))
+}
+
+/// SMIL media overlay pairing each block with the stretch of audio where it is read. None
+/// when the chapter has no blocks, as an overlay needs at least one clip.
+fn smil_document(
+    stem: &str,
+    audio_href: &str,
+    blocks: &[Block],
+    timings: &[SentenceTiming],
+    duration: f64,
+) -> Option<String> {
+    if blocks.is_empty() {
+        return None;
+    }
+
+    // (block index, first sentence index), in reading order
+    let mut matched: Vec<(usize, usize)> = Vec::new();
+    let mut cursor = 0usize;
+    for (b, block) in blocks.iter().enumerate() {
+        if let Some(i) = find_sentence(timings, cursor, &block.markdown, block.heading) {
+            matched.push((b, i));
+            cursor = i + 1;
+        }
+    }
+
+    // (block index, clip begin, clip end); without any match the whole chapter is one clip
+    // on the first block, so the audio still plays from the text
+    let clips: Vec<(usize, f64, f64)> = if matched.is_empty() {
+        vec![(0, 0.0, duration)]
+    } else {
+        matched
+            .iter()
+            .enumerate()
+            .map(|(k, &(b, i))| {
+                let begin = timings[i].start;
+                let end = match matched.get(k + 1) {
+                    Some(&(_, next)) => timings[next].start,
+                    None => timings.last().map_or(duration, |t| t.end).max(begin),
+                };
+                (b, begin, end)
+            })
+            .collect()
+    };
+
+    let mut pars = String::new();
+    for (k, &(b, begin, end)) in clips.iter().enumerate() {
+        let Some(block) = blocks.get(b) else {
+            continue;
+        };
+        pars.push_str(&format!(
+            "<par id=\"par{}\"><text src=\"{}.xhtml#{}\"/><audio src=\"{}\" clipBegin=\"{:.3}s\" clipEnd=\"{:.3}s\"/></par>\n",
+            k + 1,
+            stem,
+            block.id,
+            escape_xml(audio_href),
+            begin,
+            end
+        ));
+    }
+    Some(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<smil xmlns=\"http://www.w3.org/ns/SMIL\" xmlns:epub=\"http://www.idpf.org/2007/ops\" version=\"3.0\">\n<body>\n<seq id=\"seq1\" epub:textref=\"{}.xhtml\" epub:type=\"chapter\">\n{}</seq>\n</body>\n</smil>\n",
+        stem, pars
+    ))
+}
+
+/// `H:MM:SS.fff` clock value for `media:duration`.
+fn clock_value(secs: f64) -> String {
+    let total_ms = (secs.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        total_ms / 3_600_000,
+        total_ms / 60_000 % 60,
+        total_ms / 1000 % 60,
+        total_ms % 1000
+    )
+}
+
+fn write_epub(output: &Path, meta: &BookMeta, chapters: &[Chapter]) -> Result<()> {
+    if let Some(dir) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let file =
+        File::create(output).with_context(|| format!("failed to create {}", output.display()))?;
+    let mut zip = ZipWriter::new(file);
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // The mimetype entry must come first and be stored uncompressed
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n<rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n</container>\n",
+    )?;
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(package_document(meta, chapters).as_bytes())?;
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(nav_document(meta, chapters).as_bytes())?;
+    zip.start_file("OEBPS/style.css", deflated)?;
+    zip.write_all(STYLESHEET.as_bytes())?;
+
+    for chapter in chapters {
+        zip.start_file(format!("OEBPS/{}.xhtml", chapter.stem), deflated)?;
+        zip.write_all(chapter.xhtml.as_bytes())?;
+        let Some(overlay) = &chapter.overlay else {
+            continue;
+        };
+        zip.start_file(format!("OEBPS/{}.smil", chapter.stem), deflated)?;
+        zip.write_all(overlay.smil.as_bytes())?;
+        // Audio is already compressed (MP3) or compresses poorly (PCM)
+        let mut audio = fs::File::open(&overlay.audio)
+            .with_context(|| format!("failed to read {}", overlay.audio.display()))?;
+        // Whole-book WAVs can pass 4 GiB, which needs a ZIP64 entry
+        let large = audio.metadata()?.len() >= u32::MAX as u64;
+        zip.start_file(
+            format!("OEBPS/audio/{}", file_name(&overlay.audio)),
+            stored.large_file(large),
+        )?;
+        std::io::copy(&mut audio, &mut zip)?;
+    }
+    zip.finish()
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    Ok(())
+}
+
+fn package_document(meta: &BookMeta, chapters: &[Chapter]) -> String {
+    let mut metadata = String::new();
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut total = 0.0;
+    for (i, chapter) in chapters.iter().enumerate() {
+        let id = format!("chapter{}", i + 1);
+        let overlay_attr = match &chapter.overlay {
+            Some(overlay) => {
+                total += overlay.duration;
+                metadata.push_str(&format!(
+                    "<meta property=\"media:duration\" refines=\"#{}-overlay\">{}</meta>\n",
+                    id,
+                    clock_value(overlay.duration)
+                ));
+                manifest.push_str(&format!(
+                    "<item id=\"{id}-overlay\" href=\"{stem}.smil\" media-type=\"application/smil+xml\"/>\n<item id=\"{id}-audio\" href=\"audio/{audio}\" media-type=\"{media_type}\"/>\n",
+                    id = id,
+                    stem = chapter.stem,
+                    audio = escape_xml(&file_name(&overlay.audio)),
+                    media_type = overlay.media_type
+                ));
+                format!(" media-overlay=\"{}-overlay\"", id)
+            }
+            None => String::new(),
+        };
+        manifest.push_str(&format!(
+            "<item id=\"{}\" href=\"{}.xhtml\" media-type=\"application/xhtml+xml\"{}/>\n",
+            id,
+            escape_xml(&chapter.stem),
+            overlay_attr
+        ));
+        spine.push_str(&format!("<itemref idref=\"{}\"/>\n", id));
+    }
+    if chapters.iter().any(|c| c.overlay.is_some()) {
+        metadata.push_str(&format!(
+            "<meta property=\"media:duration\">{}</meta>\n<meta property=\"media:active-class\">{}</meta>\n",
+            clock_value(total),
+            ACTIVE_CLASS
+        ));
+    }
+    let slug: String = meta
+        .title
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<dc:identifier id=\"book-id\">urn:rust-the-audio-book:{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n{}</metadata>\n<manifest>\n<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        escape_xml(&slug),
+        escape_xml(&meta.title),
+        escape_xml(&meta.language),
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        metadata,
+        manifest,
+        spine
+    )
+}
+
+fn nav_document(meta: &BookMeta, chapters: &[Chapter]) -> String {
+    let items: String = chapters
+        .iter()
+        .map(|c| {
+            format!(
+                "<li><a href=\"{}.xhtml\">{}</a></li>\n",
+                escape_xml(&c.stem),
+                escape_xml(&c.title)
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n<head>\n<title>{}</title>\n</head>\n<body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{}</ol>\n</nav>\n</body>\n</html>\n",
+        escape_xml(&meta.title),
+        items
+    )
+}
+
+/// Render chapter Markdown as an XHTML body fragment, returning it with the blocks that
+/// carry an `id` for the media overlay.
+///
+/// Covers what the book uses: headings, paragraphs, fenced code, lists, blockquotes and pipe
+/// tables, with emphasis, inline code and links inside them. Images are shown as their alt
+/// text and other HTML is reduced to its text, so the output is always well-formed.
+fn markdown_to_xhtml(input: &str) -> (String, Vec<Block>) {
+    let re_heading = Regex::new(r"^(#{1,6})\s+(.*?)\s*#*\s*$").unwrap();
+    let re_list = Regex::new(r"^\s*(?:([-*+])|(\d+)[.)])\s+(.*)$").unwrap();
+    let re_link_def = Regex::new(r"^\s*\[[^\]^]+\]:\s*\S+").unwrap();
+    let re_img_tag = Regex::new(r#"<img\b[^>]*?\balt="([^"]*)"[^>]*>"#).unwrap();
+
+    let mut r = Renderer::default();
+    let mut lines = input.lines().peekable();
+    while let Some(line) = lines.next() {
+        let t = line.trim_start();
+
+        if t.starts_with("```") {
+            r.close();
+            let mut code = Vec::new();
+            for l in lines.by_ref() {
+                if l.trim_start().starts_with("```") {
+                    break;
+                }
+                code.push(l);
+            }
+            r.out.push_str(&format!(
+                "<pre><code>{}</code></pre>\n",
+                escape_xml(&code.join("\n"))
+            ));
+            continue;
+        }
+        if t.is_empty() {
+            // A blank line ends a paragraph or list item; lists and quotes stay open if the
+            // next line carries on with them
+            let continues_list = r.list.is_some()
+                && lines
+                    .peek()
+                    .is_some_and(|n| re_list.is_match(n) || n.starts_with("  "));
+            let continues_quote = r.quote && lines.peek().is_some_and(|n| n.starts_with('>'));
+            if continues_list || continues_quote {
+                r.end_paragraph();
+            } else {
+                r.close();
+            }
+            continue;
+        }
+        if let Some(caps) = re_heading.captures(t) {
+            r.close();
+            let level = caps[1].len();
+            let id = r.add_block(&caps[2], true);
+            r.out.push_str(&format!(
+                "<h{level} id=\"{}\">{}</h{level}>\n",
+                id,
+                inline_xhtml(&caps[2])
+            ));
+            continue;
+        }
+        if t.starts_with('|') {
+            r.close();
+            let mut rows = vec![line];
+            while let Some(next) = lines.next_if(|n| n.trim_start().starts_with('|')) {
+                rows.push(next);
+            }
+            r.table(&rows);
+            continue;
+        }
+        // `<img>` tags become their alt text first, so a line holding only an image isn't
+        // dropped as HTML
+        let with_alt;
+        let t = if re_img_tag.is_match(t) {
+            with_alt = re_img_tag.replace_all(t, "$1").into_owned();
+            with_alt.trim()
+        } else {
+            t
+        };
+        if t.is_empty()
+            || re_link_def.is_match(t)
+            || (t.starts_with('<') && strip_tags(t).trim().is_empty())
+        {
+            continue;
+        }
+        if let Some(rest) = t.strip_prefix('>') {
+            if !r.quote {
+                r.close();
+                r.out.push_str("<blockquote>\n");
+                r.quote = true;
+            }
+            let rest = rest.trim();
+            if let Some(caps) = re_heading.captures(rest) {
+                r.end_paragraph();
+                let id = r.add_block(&caps[2], true);
+                r.out.push_str(&format!(
+                    "<p id=\"{}\"><strong>{}</strong></p>\n",
+                    id,
+                    inline_xhtml(&caps[2])
+                ));
+            } else if !rest.is_empty() {
+                r.paragraph.push(rest.to_string());
+            }
+            continue;
+        }
+        if let Some(caps) = re_list.captures(line) {
+            let tag = if caps.get(1).is_some() { "ul" } else { "ol" };
+            if r.list == Some(tag) {
+                r.end_paragraph();
+            } else {
+                r.close();
+                r.out.push_str(&format!("<{}>\n", tag));
+                r.list = Some(tag);
+            }
+            r.paragraph.push(caps[3].to_string());
+            continue;
+        }
+        if r.list.is_some() && r.paragraph.is_empty() && !line.starts_with(' ') {
+            r.close();
+        }
+        r.paragraph.push(t.to_string());
+    }
+    r.close();
+    (r.out, r.blocks)
+}
+
+/// Output and open elements while rendering a chapter.
+#[derive(Default)]
+struct Renderer {
+    out: String,
+    blocks: Vec<Block>,
+    /// Lines of the paragraph or list item being collected.
+    paragraph: Vec<String>,
+    /// Open list element (`ul` / `ol`).
+    list: Option<&'static str>,
+    quote: bool,
+}
+
+impl Renderer {
+    fn add_block(&mut self, markdown: &str, heading: bool) -> String {
+        let id = format!("b{:04}", self.blocks.len() + 1);
+        self.blocks.push(Block {
+            id: id.clone(),
+            markdown: markdown.to_string(),
+            heading,
+        });
+        id
+    }
+
+    /// Emit the collected paragraph as a `<p>`, or an `<li>` inside a list.
+    fn end_paragraph(&mut self) {
+        if self.paragraph.is_empty() {
+            return;
+        }
+        let text = self.paragraph.join(" ");
+        self.paragraph.clear();
+        let id = self.add_block(&text, false);
+        let tag = if self.list.is_some() { "li" } else { "p" };
+        self.out.push_str(&format!(
+            "<{tag} id=\"{}\">{}</{tag}>\n",
+            id,
+            inline_xhtml(&text)
+        ));
+    }
+
+    /// End the paragraph and close any open list or blockquote.
+    fn close(&mut self) {
+        self.end_paragraph();
+        if let Some(tag) = self.list.take() {
+            self.out.push_str(&format!("</{}>\n", tag));
+        }
+        if self.quote {
+            self.out.push_str("</blockquote>\n");
+            self.quote = false;
+        }
+    }
+
+    /// Pipe table rows as an XHTML table; the header row and each body row become blocks.
+    fn table(&mut self, rows: &[&str]) {
+        let cells = |row: &str| -> Vec<String> {
+            row.trim()
+                .trim_matches('|')
+                .split('|')
+                .map(|c| c.trim().to_string())
+                .collect()
+        };
+        let is_separator = |row: &str| {
+            row.chars()
+                .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+        };
+        self.out.push_str("<table>\n");
+        for (i, row) in rows.iter().enumerate() {
+            if is_separator(row) {
+                continue;
+            }
+            let tag = if i == 0 { "th" } else { "td" };
+            let id = self.add_block(&cells(row).join(" "), false);
+            let mut tr = format!("<tr id=\"{}\">", id);
+            for cell in cells(row) {
+                tr.push_str(&format!("<{tag}>{}</{tag}>", inline_xhtml(&cell)));
+            }
+            tr.push_str("</tr>\n");
+            self.out.push_str(&tr);
+        }
+        self.out.push_str("</table>\n");
+    }
+}
+
+/// Inline Markdown to XHTML: code spans, emphasis and links; images become their alt text
+/// and raw HTML tags are dropped.
+fn inline_xhtml(text: &str) -> String {
+    // Code spans are escaped verbatim; everything between them gets emphasis and links
+    let re_code = Regex::new(r"`+([^`]+)`+").unwrap();
+    let mut out = String::new();
+    let mut last = 0;
+    for caps in re_code.captures_iter(text) {
+        let m = caps.get(0).unwrap();
+        out.push_str(&inline_spans(&text[last..m.start()]));
+        out.push_str(&format!("<code>{}</code>", escape_xml(caps[1].trim())));
+        last = m.end();
+    }
+    out.push_str(&inline_spans(&text[last..]));
+    out
+}
+
+fn inline_spans(text: &str) -> String {
+    let re_image = Regex::new(r"!\[([^\]]*)\](?:\([^)]*\)|\[[^\]]*\])").unwrap();
+    let re_img_tag = Regex::new(r#"<img\b[^>]*?\balt="([^"]*)"[^>]*>"#).unwrap();
+    let re_footnote = Regex::new(r"\[\^[^\]]+\]").unwrap();
+    let re_link = Regex::new(r"\[([^\]]+)\]\(([^)\s]+)[^)]*\)").unwrap();
+    let re_ref = Regex::new(r"\[([^\]]+)\]\[[^\]]*\]").unwrap();
+    let re_strong = Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").unwrap();
+    let re_em = Regex::new(r"\*([^*\s][^*]*?)\*|\b_([^_\s][^_]*?)_\b").unwrap();
+
+    let s = re_image.replace_all(text, "$1");
+    let s = re_img_tag.replace_all(&s, "$1");
+    let s = re_footnote.replace_all(&s, "");
+    let s = escape_xml(&strip_tags(&s));
+    let s = re_link.replace_all(&s, |caps: &regex::Captures| {
+        // Only external links resolve inside the EPUB
+        if caps[2].starts_with("http://") || caps[2].starts_with("https://") {
+            format!("<a href=\"{}\">{}</a>", &caps[2], &caps[1])
+        } else {
+            caps[1].to_string()
+        }
+    });
+    let s = re_ref.replace_all(&s, "$1");
+    let s = re_strong.replace_all(&s, "<strong>$1$2</strong>");
+    re_em.replace_all(&s, "<em>$1$2</em>").into_owned()
+}
+
+/// Text of `html` with tags and comments removed.
+fn strip_tags(html: &str) -> String {
+    let re_tag = Regex::new(r"<!--.*?-->|</?[A-Za-z][^>]*>").unwrap();
+    re_tag.replace_all(html, "").into_owned()
+}
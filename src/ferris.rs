@@ -0,0 +1,82 @@
+use regex::Regex;
+
+/// The Rust Book's Ferris markers: code block attribute / icon file name, and what it means.
+const FERRIS_MARKERS: &[(&str, &str)] = &[
+    ("does_not_compile", "does not compile"),
+    ("panics", "panics"),
+    (
+        "not_desired_behavior",
+        "does not produce the desired behavior",
+    ),
+];
+
+/// Turn Ferris markers into spoken warnings attached to the code they describe.
+///
+/// A fence such as ` ```rust,ignore,does_not_compile ` gets "Warning: the following code does
+/// not compile." on the line before it, so it is read right before the code narration. Ferris
+/// images (`<img class="ferris" src="img/ferris/panics.svg">`) become the same warning, or,
+/// for the `ferris-explain` icons in the book's introduction, a statement of what the icon means.
+/// Must run before code blocks are summarized, while fence info strings are still present.
+pub fn mark_ferris_warnings(input: &str) -> String {
+    let re_img = Regex::new(r"<img\b[^>]*>").unwrap();
+
+    let mut out: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    for line in input.lines() {
+        let t = line.trim_start();
+        if t.starts_with("```") {
+            if !in_fence && let Some(meaning) = fence_marker(t) {
+                let warning = warning(meaning);
+                // An icon right before the fence already produced the warning
+                let last = out.iter().rev().find(|l| !l.trim().is_empty());
+                if last.is_none_or(|l| l.trim() != warning) {
+                    out.push(warning);
+                    out.push(String::new());
+                }
+            }
+            in_fence = !in_fence;
+            out.push(line.to_string());
+            continue;
+        }
+        if in_fence || !re_img.is_match(line) {
+            out.push(line.to_string());
+            continue;
+        }
+        let replaced = re_img.replace_all(line, |caps: &regex::Captures| {
+            let tag = &caps[0];
+            match icon_marker(tag) {
+                Some(meaning) if tag.contains("ferris-explain") => {
+                    format!("Ferris icon: this code {}.", meaning)
+                }
+                Some(meaning) => warning(meaning),
+                None => tag.to_string(),
+            }
+        });
+        out.push(replaced.into_owned());
+    }
+    out.join("\n")
+}
+
+fn warning(meaning: &str) -> String {
+    format!("Warning: the following code {}.", meaning)
+}
+
+/// Meaning of a Ferris attribute in a fence's info string (`rust,panics`).
+fn fence_marker(fence: &str) -> Option<&'static str> {
+    let info = fence.trim_start_matches('`');
+    info.split([',', ' ', '{', '}'])
+        .find_map(|attr| FERRIS_MARKERS.iter().find(|(name, _)| *name == attr.trim()))
+        .map(|(_, meaning)| *meaning)
+}
+
+/// Meaning of a Ferris `<img>` tag, recognized by its `ferris` class or icon path and the
+/// marker name in its file name.
+fn icon_marker(tag: &str) -> Option<&'static str> {
+    if !tag.contains("ferris") {
+        return None;
+    }
+    FERRIS_MARKERS
+        .iter()
+        .find(|(name, _)| tag.contains(name))
+        .map(|(_, meaning)| *meaning)
+}
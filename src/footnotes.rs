@@ -0,0 +1,314 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Where footnote text ends up in the narration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FootnotePolicy {
+    /// Read the note right after the sentence that references it.
+    #[default]
+    Inline,
+    /// Collect the notes and read them before the next heading (or at the end of the chapter).
+    SectionEnd,
+    /// Remove references and definitions entirely.
+    Drop,
+}
+
+impl FootnotePolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "inline" | "in-place" => Some(FootnotePolicy::Inline),
+            "section-end" | "section" | "end" => Some(FootnotePolicy::SectionEnd),
+            "drop" | "none" => Some(FootnotePolicy::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// Resolve `[^label]` references and `[^label]: text` definitions according to `policy`.
+/// Definitions are always removed from where they were written; references are replaced by
+/// the note text (inline), by nothing (drop), or gathered into a "Footnotes" paragraph
+/// placed before the next heading (section end). Each note is read at most once.
+///
+/// Inline notes are placed per paragraph rather than per line, so a sentence hard-wrapped
+/// across several lines gets its note after the sentence, not at the end of the first line.
+pub fn resolve_footnotes(input: &str, policy: FootnotePolicy) -> String {
+    let (body, defs) = take_definitions(input);
+    let re_ref = Regex::new(r"\[\^([^\]\s]+)\]").unwrap();
+    let re_block = Regex::new(r"^([-*+]\s|\d+[.)]\s|>|\||<)").unwrap();
+    let mut notes = Notes {
+        policy,
+        re_ref,
+        defs,
+        pending: Vec::new(),
+        spoken: Vec::new(),
+    };
+
+    let mut out: Vec<String> = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+
+    for line in body.lines() {
+        let t = line.trim_start();
+        if in_fence || t.starts_with("```") {
+            notes.resolve_paragraph(&mut paragraph, &mut out);
+            if t.starts_with("```") {
+                in_fence = !in_fence;
+            }
+            out.push(line.to_string());
+            continue;
+        }
+
+        if t.is_empty() || t.starts_with('#') || re_block.is_match(t) {
+            notes.resolve_paragraph(&mut paragraph, &mut out);
+        }
+        if t.starts_with('#') {
+            if !notes.pending.is_empty() {
+                out.push(String::new());
+                out.push(notes.section_notes());
+                out.push(String::new());
+            }
+            notes.resolve_paragraph(&mut vec![line], &mut out);
+        } else if t.is_empty() {
+            out.push(line.to_string());
+        } else {
+            paragraph.push(line);
+        }
+    }
+    notes.resolve_paragraph(&mut paragraph, &mut out);
+
+    if !notes.pending.is_empty() {
+        out.push(String::new());
+        out.push(notes.section_notes());
+    }
+
+    out.join("\n")
+}
+
+/// Footnote state carried through the chapter.
+struct Notes {
+    policy: FootnotePolicy,
+    re_ref: Regex,
+    defs: HashMap<String, String>,
+    /// Labels waiting for the next heading (section-end policy).
+    pending: Vec<String>,
+    /// Labels already read, so each note is read once.
+    spoken: Vec<String>,
+}
+
+impl Notes {
+    /// Resolve the references in one paragraph's lines, push the result to `out` and clear
+    /// `lines`. With the inline policy a paragraph that cites notes is joined into one line.
+    fn resolve_paragraph(&mut self, lines: &mut Vec<&str>, out: &mut Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+        if !lines.iter().any(|l| self.re_ref.is_match(l)) {
+            out.extend(lines.drain(..).map(str::to_string));
+            return;
+        }
+        match self.policy {
+            FootnotePolicy::Drop => {
+                for line in lines.drain(..) {
+                    out.push(self.re_ref.replace_all(line, "").into_owned());
+                }
+            }
+            FootnotePolicy::SectionEnd => {
+                for line in lines.drain(..) {
+                    for caps in self.re_ref.captures_iter(line) {
+                        let label = caps[1].to_string();
+                        if self.defs.contains_key(&label) && !self.spoken.contains(&label) {
+                            self.spoken.push(label.clone());
+                            self.pending.push(label);
+                        }
+                    }
+                    out.push(self.re_ref.replace_all(line, "").into_owned());
+                }
+            }
+            FootnotePolicy::Inline => {
+                let mut joined = lines[0].to_string();
+                for line in &lines[1..] {
+                    joined.push(' ');
+                    joined.push_str(line.trim_start());
+                }
+                lines.clear();
+                out.push(self.inline_notes(&joined));
+            }
+        }
+    }
+
+    /// Remove references from `text` and insert each note after the sentence that cites it.
+    /// A reference right after a sentence end (`First.[^1] Second.`) belongs to that
+    /// sentence; any other waits for the next sentence end.
+    fn inline_notes(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut queued: Vec<String> = Vec::new();
+        let mut last = 0usize;
+        let mut after_sentence = false;
+
+        for caps in self.re_ref.captures_iter(text) {
+            let m = caps.get(0).expect("match 0 always present");
+            let segment = &text[last..m.start()];
+            flush_sentences(segment, &mut out, &mut queued);
+            last = m.end();
+            // Consecutive references (`end.[^1][^2]`) share the first one's position
+            if !segment.is_empty() {
+                after_sentence = segment.ends_with(['.', '!', '?']);
+            }
+
+            let label = caps[1].to_string();
+            if let Some(note) = self.defs.get(&label)
+                && !self.spoken.contains(&label)
+            {
+                self.spoken.push(label);
+                if after_sentence {
+                    push_note(&mut out, note);
+                } else {
+                    queued.push(note.clone());
+                }
+            }
+        }
+        flush_sentences(&text[last..], &mut out, &mut queued);
+
+        // No sentence end after the reference: read the notes at the end of the paragraph
+        for note in queued.drain(..) {
+            push_note(&mut out, &note);
+        }
+        out
+    }
+
+    fn section_notes(&mut self) -> String {
+        let mut out = String::from("Footnotes.");
+        for label in self.pending.drain(..) {
+            if let Some(text) = self.defs.get(&label) {
+                out.push_str(&format!(
+                    "\nFootnote {}: {}.",
+                    label,
+                    text.trim_end_matches('.')
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Split footnote definitions (including indented continuation lines) out of the text.
+/// Code fences are copied through untouched.
+fn take_definitions(input: &str) -> (String, HashMap<String, String>) {
+    let re_def = Regex::new(r"^\s{0,3}\[\^([^\]\s]+)\]:\s*(.*)$").unwrap();
+    let mut defs: HashMap<String, String> = HashMap::new();
+    let mut body: Vec<&str> = Vec::new();
+    let mut current: Option<String> = None;
+    let mut in_fence = false;
+
+    for line in input.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            current = None;
+            body.push(line);
+            continue;
+        }
+        if in_fence {
+            body.push(line);
+            continue;
+        }
+        if let Some(caps) = re_def.captures(line) {
+            let label = caps[1].to_string();
+            defs.insert(label.clone(), caps[2].trim().to_string());
+            current = Some(label);
+            continue;
+        }
+        if let Some(label) = &current {
+            let continues = line.starts_with("    ") || line.starts_with('\t');
+            if continues {
+                let text = defs.entry(label.clone()).or_default();
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(line.trim());
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            current = None;
+        }
+        body.push(line);
+    }
+    (body.join("\n"), defs)
+}
+
+/// Copy `segment` into `out`, emitting queued notes at the first sentence end.
+fn flush_sentences(segment: &str, out: &mut String, queued: &mut Vec<String>) {
+    if queued.is_empty() {
+        out.push_str(segment);
+        return;
+    }
+    let bytes = segment.as_bytes();
+    for (i, ch) in segment.char_indices() {
+        if matches!(ch, '.' | '!' | '?') {
+            let next = bytes.get(i + 1).copied();
+            if next.is_none() || next == Some(b' ') {
+                out.push_str(&segment[..=i]);
+                for note in queued.drain(..) {
+                    push_note(out, &note);
+                }
+                out.push_str(&segment[i + 1..]);
+                return;
+            }
+        }
+    }
+    out.push_str(segment);
+}
+
+fn push_note(out: &mut String, note: &str) {
+    if !out.ends_with(' ') && !out.is_empty() {
+        out.push(' ');
+    }
+    out.push_str("Footnote: ");
+    out.push_str(note.trim_end_matches('.'));
+    out.push('.');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "# One\n\nSome text[^a] wraps\nonto here. Next one.\n\n```\nx[^a]\n```\n\n[^a]: The note.\n\n# Two\n\nMore.\n";
+
+    #[test]
+    fn inline_reads_note_after_the_wrapped_sentence() {
+        assert_eq!(
+            resolve_footnotes(DOC, FootnotePolicy::Inline),
+            "# One\n\nSome text wraps onto here. Footnote: The note. Next one.\n\n```\nx[^a]\n```\n\n# Two\n\nMore."
+        );
+    }
+
+    #[test]
+    fn section_end_reads_notes_before_the_next_heading() {
+        assert_eq!(
+            resolve_footnotes(DOC, FootnotePolicy::SectionEnd),
+            "# One\n\nSome text wraps\nonto here. Next one.\n\n```\nx[^a]\n```\n\n\nFootnotes.\nFootnote a: The note.\n\n# Two\n\nMore."
+        );
+    }
+
+    #[test]
+    fn drop_removes_markers_and_definitions() {
+        assert_eq!(
+            resolve_footnotes(DOC, FootnotePolicy::Drop),
+            "# One\n\nSome text wraps\nonto here. Next one.\n\n```\nx[^a]\n```\n\n# Two\n\nMore."
+        );
+    }
+
+    #[test]
+    fn each_note_is_read_once() {
+        let doc = "A[^a]. B[^a]. C[^b].\n\n[^a]: Note A.\n[^b]: Note B.\n";
+        assert_eq!(
+            resolve_footnotes(doc, FootnotePolicy::Inline),
+            "A. Footnote: Note A. B. C. Footnote: Note B."
+        );
+        assert_eq!(
+            resolve_footnotes(doc, FootnotePolicy::SectionEnd),
+            "A. B. C.\n\nFootnotes.\nFootnote a: Note A.\nFootnote b: Note B."
+        );
+    }
+}
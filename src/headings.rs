@@ -0,0 +1,149 @@
+use regex::Regex;
+
+/// How headings are announced and how much silence surrounds them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeadingOptions {
+    /// Silence before each heading (except at the very start of a chapter).
+    pub pause_before_ms: u32,
+    /// Silence between a heading and the text that follows it.
+    pub pause_after_ms: u32,
+}
+
+impl Default for HeadingOptions {
+    fn default() -> Self {
+        Self {
+            pause_before_ms: 1200,
+            pause_after_ms: 0,
+        }
+    }
+}
+
+/// A run of Markdown that starts at a heading (or at the top of the chapter).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    /// The heading line's text, if the section starts with one.
+    pub heading: Option<String>,
+    pub level: usize,
+    pub markdown: String,
+    /// First and last line (1-based, inclusive) of the section in the split text.
+    pub lines: (usize, usize),
+}
+
+/// Rewrite ATX headings into spoken announcements.
+///
+/// The file's first heading, at whatever level, is the chapter or section heading and uses
+/// its SUMMARY number: `## Data Types` opening `ch03-02` becomes
+/// `## Chapter 3, section 2: Data Types`. Headings one level below it are counted
+/// (`### Subsection 1: Scalar Types`), restarting after each heading at its level or above;
+/// deeper headings keep their plain title.
+pub fn announce_headings(input: &str, number: Option<&[u32]>) -> String {
+    let re_heading = Regex::new(r"^(\s*)(#{1,6})\s+(.*?)\s*#*\s*$").unwrap();
+    let mut out = Vec::new();
+    let mut in_fence = false;
+    let mut base_level: Option<usize> = None;
+    // Headings seen per level since the last heading above that level
+    let mut counters = [0u32; 7];
+    for line in input.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            out.push(line.to_string());
+            continue;
+        }
+        let Some(caps) = re_heading.captures(line) else {
+            out.push(line.to_string());
+            continue;
+        };
+        let level = caps[2].len();
+        let title = caps[3].trim();
+        counters[level] += 1;
+        counters[level + 1..].fill(0);
+        let spoken = match base_level {
+            None => {
+                base_level = Some(level);
+                match number {
+                    Some(n) if !n.is_empty() => format!("{}: {}", number_label(n), title),
+                    _ => title.to_string(),
+                }
+            }
+            Some(base) if level == base + 1 => {
+                format!("Subsection {}: {}", counters[level], title)
+            }
+            Some(_) => title.to_string(),
+        };
+        out.push(format!("{}{} {}", &caps[1], &caps[2], spoken));
+    }
+    out.join("\n")
+}
+
+/// "Chapter 3", "Chapter 3, section 2", "Chapter 3, section 2, part 1".
+pub fn number_label(number: &[u32]) -> String {
+    let names = ["Chapter", "section", "part"];
+    number
+        .iter()
+        .enumerate()
+        .map(|(i, n)| match names.get(i) {
+            Some(name) => format!("{} {}", name, n),
+            None => n.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Split Markdown into sections at each ATX heading outside code fences.
+pub fn split_sections(input: &str) -> Vec<Section> {
+    let re_heading = Regex::new(r"^\s*(#{1,6})\s+(.*?)\s*#*\s*$").unwrap();
+    let mut sections = Vec::new();
+    let mut current = Section {
+        heading: None,
+        level: 0,
+        markdown: String::new(),
+        lines: (1, 0),
+    };
+    let mut in_fence = false;
+    for (i, line) in input.lines().enumerate() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if !in_fence && let Some(caps) = re_heading.captures(line) {
+            if current.heading.is_some() || !current.markdown.trim().is_empty() {
+                sections.push(current);
+            }
+            current = Section {
+                heading: Some(caps[2].to_string()),
+                level: caps[1].len(),
+                markdown: String::new(),
+                lines: (i + 1, i),
+            };
+        }
+        current.markdown.push_str(line);
+        current.markdown.push('\n');
+        current.lines.1 = i + 1;
+    }
+    if current.heading.is_some() || !current.markdown.trim().is_empty() {
+        sections.push(current);
+    }
+    sections
+}
+
+/// The sections of the chapter's source Markdown that `sections` were produced from. The
+/// processed text may differ from the source (includes expanded, code summarized, headings
+/// announced), so sections are paired by heading order: the nth heading with the nth source
+/// heading, and the text before the first heading with its source counterpart. Sections
+/// without a counterpart get None.
+pub fn source_sections(sections: &[Section], source: &str) -> Vec<Option<Section>> {
+    let source_sections = split_sections(source);
+    let source_intro = source_sections
+        .iter()
+        .find(|s| s.heading.is_none())
+        .cloned();
+    let mut source_headed = source_sections.into_iter().filter(|s| s.heading.is_some());
+    sections
+        .iter()
+        .map(|s| match s.heading {
+            Some(_) => source_headed.next(),
+            None => source_intro.clone(),
+        })
+        .collect()
+}
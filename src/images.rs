@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::diagnostics::{Diagnostics, Strictness};
+use crate::tts::GeminiClient;
+
+/// Replace local images with spoken descriptions.
+///
+/// For each Markdown `![alt](src)` or HTML `<img src alt>` outside code fences that points to a
+/// local file, the description comes from, in order:
+/// - a sidecar file next to the image (`img/trpl04-01.description.txt` for `img/trpl04-01.svg`),
+/// - the cache in `cache_dir`, unless the image has changed since it was written,
+/// - the multimodal model, whose answer is then written to the cache.
+///
+/// Cache entries are plain text files, one per image, so they can be reviewed and edited; an
+/// edited entry is kept as long as it is newer than the image. The image is replaced by
+/// "Figure: alt. description". Remote images and failures are left as they are (read as their
+/// alt text later) and failures are reported through `diagnostics`.
+pub async fn describe_images(
+    client: &GeminiClient,
+    markdown_path: &Path,
+    input: &str,
+    cache_dir: &Path,
+    strictness: Strictness,
+    diagnostics: &mut Diagnostics,
+) -> Result<(String, usize)> {
+    let re_image = Regex::new(
+        r#"!\[(?P<alt>[^\]]*)\]\(\s*<?(?P<src>[^)\s>]+)>?(?:\s+"[^"]*")?\s*\)|<img\b(?P<attrs>[^>]*)>"#,
+    )
+    .unwrap();
+    let base_dir = markdown_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut out: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    let mut count = 0usize;
+    for line in input.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence || !re_image.is_match(line) {
+            out.push(line.to_string());
+            continue;
+        }
+
+        let mut new_line = String::new();
+        let mut last = 0usize;
+        for caps in re_image.captures_iter(line) {
+            let m = caps.get(0).expect("match 0 always present");
+            new_line.push_str(&line[last..m.start()]);
+            last = m.end();
+
+            let Some((src, alt)) = image_source(&caps) else {
+                new_line.push_str(m.as_str());
+                continue;
+            };
+            if is_remote(&src) {
+                new_line.push_str(m.as_str());
+                continue;
+            }
+            let image_path = base_dir.join(&src);
+            match description(client, &image_path, &src, &alt, cache_dir).await {
+                Ok(text) => {
+                    count += 1;
+                    new_line.push_str(&figure_text(&alt, &text));
+                }
+                Err(e) => {
+                    diagnostics.report(
+                        strictness,
+                        format!("description of image {} failed: {:#}", src, e),
+                    )?;
+                    new_line.push_str(m.as_str());
+                }
+            }
+        }
+        new_line.push_str(&line[last..]);
+        out.push(new_line);
+    }
+    Ok((out.join("\n"), count))
+}
+
+/// `(src, alt)` of a matched image, or None for an `<img>` without a `src`.
+fn image_source(caps: &Captures) -> Option<(String, String)> {
+    if let Some(attrs) = caps.name("attrs") {
+        let src = html_attr(attrs.as_str(), "src")?;
+        let alt = html_attr(attrs.as_str(), "alt").unwrap_or_default();
+        return Some((src, alt));
+    }
+    Some((caps["src"].to_string(), caps["alt"].trim().to_string()))
+}
+
+fn html_attr(attrs: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(r#"(?i)\b{}\s*=\s*(?:"([^"]*)"|'([^']*)')"#, name)).unwrap();
+    let caps = re.captures(attrs)?;
+    caps.get(1)
+        .or_else(|| caps.get(2))
+        .map(|m| m.as_str().trim().to_string())
+}
+
+fn is_remote(src: &str) -> bool {
+    let lower = src.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://") || lower.starts_with("data:")
+}
+
+fn figure_text(alt: &str, description: &str) -> String {
+    let description = description.split_whitespace().collect::<Vec<_>>().join(" ");
+    let alt = alt.trim().trim_end_matches('.');
+    if alt.is_empty() {
+        format!("Figure: {}", description)
+    } else {
+        format!("Figure: {}. {}", alt, description)
+    }
+}
+
+/// Sidecar, then fresh cache entry, then the model (caching its answer).
+async fn description(
+    client: &GeminiClient,
+    image_path: &Path,
+    src: &str,
+    alt: &str,
+    cache_dir: &Path,
+) -> Result<String> {
+    let sidecar = image_path.with_extension("description.txt");
+    if sidecar.exists() {
+        return fs::read_to_string(&sidecar)
+            .with_context(|| format!("failed to read {}", sidecar.display()));
+    }
+
+    let image_modified = fs::metadata(image_path)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("image not found: {}", image_path.display()))?;
+    let cache_path = cache_path(cache_dir, src);
+    if let Ok(cached_modified) = fs::metadata(&cache_path).and_then(|m| m.modified())
+        && cached_modified >= image_modified
+    {
+        return fs::read_to_string(&cache_path)
+            .with_context(|| format!("failed to read {}", cache_path.display()));
+    }
+
+    let bytes = fs::read(image_path)
+        .with_context(|| format!("failed to read image {}", image_path.display()))?;
+    println!("Describing image {} ({} bytes)", src, bytes.len());
+    let t0 = Instant::now();
+    let text = client
+        .describe_image(&bytes, image_mime(image_path), alt)
+        .await?;
+    let text = text.trim().to_string();
+    println!(
+        "Image {} described ({} chars) in {:?}",
+        src,
+        text.chars().count(),
+        t0.elapsed()
+    );
+
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("failed to create {}", cache_dir.display()))?;
+    fs::write(&cache_path, &text)
+        .with_context(|| format!("failed to write {}", cache_path.display()))?;
+    Ok(text)
+}
+
+/// One text file per image, named after its path: `img/trpl04-01.svg` -> `img_trpl04-01.svg.txt`.
+fn cache_path(cache_dir: &Path, src: &str) -> PathBuf {
+    let name = src
+        .trim_start_matches("./")
+        .replace(['/', '\\'], "_")
+        .replace("..", "up");
+    cache_dir.join(format!("{}.txt", name))
+}
+
+fn image_mime(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Context, Result, anyhow};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Lexicon shipped with the crate, tuned for the Rust Book.
+pub const DEFAULT_LEXICON: &str = include_str!("../lexicon/rust-book.toml");
+
+#[derive(Debug, Deserialize)]
+struct LexiconFile {
+    #[serde(default, rename = "entry")]
+    entries: Vec<LexiconEntry>,
+}
+
+/// One lexicon rule as written in the TOML file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LexiconEntry {
+    /// Literal term, matched as a whole word.
+    pub term: Option<String>,
+    /// Regular expression, used as-is (mutually exclusive with `term`).
+    pub regex: Option<String>,
+    /// Spoken replacement. For `regex` entries, `$1`-style group references are expanded.
+    pub say: String,
+    /// Optional IPA transcription, emitted as an SSML `<phoneme>` when SSML output is used.
+    pub ipa: Option<String>,
+    #[serde(default = "default_true")]
+    pub case_sensitive: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Compiled pronunciation rules, applied in file order to the text sent to TTS.
+#[derive(Clone, Debug, Default)]
+pub struct Lexicon {
+    rules: Vec<(Regex, LexiconEntry)>,
+}
+
+impl Lexicon {
+    /// Parse a lexicon from TOML text.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: LexiconFile = toml::from_str(text).context("invalid lexicon TOML")?;
+        let mut rules = Vec::with_capacity(file.entries.len());
+        for (i, entry) in file.entries.into_iter().enumerate() {
+            let pattern = match (&entry.term, &entry.regex) {
+                (Some(term), None) => term_pattern(term),
+                (None, Some(re)) => re.clone(),
+                _ => {
+                    return Err(anyhow!(
+                        "lexicon entry #{} must have exactly one of `term` or `regex`",
+                        i + 1
+                    ));
+                }
+            };
+            let re = RegexBuilder::new(&pattern)
+                .case_insensitive(!entry.case_sensitive)
+                .build()
+                .with_context(|| format!("invalid pattern in lexicon entry #{}", i + 1))?;
+            rules.push((re, entry));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Load a lexicon file from disk.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read lexicon {}", path.display()))?;
+        Self::from_toml(&text).with_context(|| format!("in lexicon {}", path.display()))
+    }
+
+    /// The built-in Rust Book lexicon.
+    pub fn rust_book() -> Self {
+        Self::from_toml(DEFAULT_LEXICON).expect("bundled lexicon is valid")
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Compiled rules in application order.
+    pub fn rules(&self) -> impl Iterator<Item = (&Regex, &LexiconEntry)> {
+        self.rules.iter().map(|(re, e)| (re, e))
+    }
+
+    /// Rewrite every matching term in `text` to its spoken form.
+    pub fn apply(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        for (re, entry) in &self.rules {
+            let replaced = if entry.regex.is_some() {
+                re.replace_all(&out, entry.say.as_str())
+            } else {
+                re.replace_all(&out, regex::NoExpand(&entry.say))
+            };
+            out = replaced.into_owned();
+        }
+        out
+    }
+}
+
+/// Whole-word pattern for a literal term; word boundaries are only added on sides
+/// where the term starts or ends with a word character (so `&str` and `Cargo.toml` work).
+fn term_pattern(term: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut p = String::new();
+    if term.chars().next().is_some_and(is_word) {
+        p.push_str(r"\b");
+    }
+    p.push_str(&regex::escape(term));
+    if term.chars().last().is_some_and(is_word) {
+        p.push_str(r"\b");
+    }
+    p
+}
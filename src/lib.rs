@@ -1,5 +1,23 @@
+pub mod align;
 pub mod audio;
+pub mod callouts;
+pub mod chunking;
+pub mod diagnostics;
+pub mod epub;
+pub mod ferris;
+pub mod footnotes;
+pub mod headings;
+pub mod images;
+pub mod lexicon;
 pub mod markdown;
+pub mod mdbook;
+pub mod normalize;
+pub mod podcast;
+pub mod serve;
+pub mod ssml;
+pub mod subtitles;
+pub mod summary;
+pub mod tables;
 pub mod tts;
 pub mod util;
 
@@ -8,14 +8,32 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-use rust_the_audio_book::audio::{
-    guess_audio_extension, merge_concat, merge_mp3, try_merge_wav, try_silence_ratio_from_mime,
+use rust_the_audio_book::align::{SentenceTiming, align_sentences, write_timing_map};
+use rust_the_audio_book::audio::ChapterAudioWriter;
+use rust_the_audio_book::callouts::narrate_callouts;
+use rust_the_audio_book::chunking::{Chunk, chunk_sections, split_sentences, write_chunk_map};
+use rust_the_audio_book::diagnostics::{Diagnostics, Strictness};
+use rust_the_audio_book::epub::export_epub;
+use rust_the_audio_book::ferris::mark_ferris_warnings;
+use rust_the_audio_book::footnotes::{FootnotePolicy, resolve_footnotes};
+use rust_the_audio_book::headings::{
+    HeadingOptions, announce_headings, source_sections, split_sections,
 };
+use rust_the_audio_book::images::describe_images;
+use rust_the_audio_book::lexicon::Lexicon;
 use rust_the_audio_book::markdown::{
-    expand_includes, replace_code_blocks_with_summaries, sanitize_markdown_for_tts,
-    split_into_chunks_by_paragraph,
+    expand_includes, extract_chapter_meta, prepend_title_announcement,
+    replace_code_blocks_with_summaries, sanitize_markdown_for_tts,
 };
-use rust_the_audio_book::tts::{AVAILABLE_VOICES, GeminiClient};
+use rust_the_audio_book::mdbook::{run_preprocessor, supports_renderer};
+use rust_the_audio_book::normalize::{Locale, Normalizer};
+use rust_the_audio_book::podcast::{PodcastOptions, write_podcast};
+use rust_the_audio_book::serve::serve;
+use rust_the_audio_book::ssml::{escape_xml, markdown_to_ssml, ssml_to_text, wrap_ssml_document};
+use rust_the_audio_book::subtitles::{Cue, write_subtitles};
+use rust_the_audio_book::summary::Summary;
+use rust_the_audio_book::tables::narrate_tables;
+use rust_the_audio_book::tts::{AVAILABLE_VOICES, GeminiClient, TextFormat};
 use rust_the_audio_book::util::now_ts;
 
 #[tokio::main]
@@ -29,9 +47,117 @@ async fn main() -> Result<()> {
         .next()
         .unwrap_or_else(|| "rust-the-audio-book".to_string());
 
+    // mdBook preprocessor mode: `rust-the-audio-book mdbook [supports <renderer>]`
+    let mut args = args.peekable();
+    if args.peek().is_some_and(|a| a == "mdbook") {
+        args.next();
+        if args.next().as_deref() == Some("supports") {
+            let renderer = args.next().unwrap_or_default();
+            std::process::exit(if supports_renderer(&renderer) { 0 } else { 1 });
+        }
+        return run_preprocessor();
+    }
+
+    // EPUB export: `rust-the-audio-book epub [OUTPUT]`, from book/src and the generated audio
+    if args.peek().is_some_and(|a| a == "epub") {
+        args.next();
+        let output = PathBuf::from(args.next().unwrap_or_else(|| "audio/book.epub".to_string()));
+        let with_audio = export_epub(Path::new("book/src"), Path::new("audio"), &output)?;
+        println!(
+            "Wrote {} ({} chapter(s) with read-along audio)",
+            output.display(),
+            with_audio
+        );
+        return Ok(());
+    }
+
+    // Podcast feed: `rust-the-audio-book podcast --base-url <URL> [...]`, from the audio in audio/
+    if args.peek().is_some_and(|a| a == "podcast") {
+        args.next();
+        let mut opts = PodcastOptions::default();
+        while let Some(arg) = args.next() {
+            let value = |args: &mut std::iter::Peekable<env::Args>| {
+                args.next().unwrap_or_else(|| {
+                    eprintln!("error: {} requires a value", arg);
+                    std::process::exit(2);
+                })
+            };
+            match arg.as_str() {
+                "--base-url" => opts.base_url = value(&mut args),
+                "--title" => opts.title = Some(value(&mut args)),
+                "--author" => opts.author = Some(value(&mut args)),
+                "--description" => opts.description = Some(value(&mut args)),
+                "--image" => opts.image = Some(value(&mut args)),
+                other => {
+                    eprintln!("error: unknown podcast option '{}'", other);
+                    print_help(&program);
+                    std::process::exit(2);
+                }
+            }
+        }
+        if opts.base_url.is_empty() {
+            eprintln!("error: podcast requires --base-url <URL> (where audio/ will be hosted)");
+            std::process::exit(2);
+        }
+        let count = write_podcast(Path::new("book/src"), Path::new("audio"), &opts)?;
+        println!(
+            "Wrote audio/feed.xml and audio/index.html ({} episode(s))",
+            count
+        );
+        return Ok(());
+    }
+
+    // Local server: `rust-the-audio-book serve [--bind <ADDR>] [--port <N>]`, hosting audio/
+    if args.peek().is_some_and(|a| a == "serve") {
+        args.next();
+        let mut bind = "0.0.0.0".to_string();
+        let mut port: u16 = 8080;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bind" => {
+                    bind = args.next().unwrap_or_else(|| {
+                        eprintln!("error: --bind requires an address");
+                        std::process::exit(2);
+                    })
+                }
+                "--port" => {
+                    port = args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| {
+                        eprintln!("error: --port requires a port number");
+                        std::process::exit(2);
+                    })
+                }
+                other => {
+                    eprintln!("error: unknown serve option '{}'", other);
+                    print_help(&program);
+                    std::process::exit(2);
+                }
+            }
+        }
+        let addr = format!("{}:{}", bind, port)
+            .parse()
+            .with_context(|| format!("invalid bind address '{}'", bind))?;
+        return serve(
+            addr,
+            Path::new("book/src"),
+            Path::new("audio"),
+            PodcastOptions::default(),
+        )
+        .await;
+    }
+
     let mut voice_name: String = "Zephyr".to_string();
     let mut file_args: Vec<PathBuf> = Vec::new();
     let mut api_key_arg: Option<String> = None;
+    let mut strictness = Strictness::default();
+    let mut table_summary_rows: Option<usize> = None;
+    let mut footnote_policy = FootnotePolicy::default();
+    let mut lexicon_path: Option<PathBuf> = None;
+    let mut write_ssml = false;
+    let mut describe_images = true;
+    let mut streaming = true;
+    let mut locale = Locale::default();
+    let mut headings = HeadingOptions::default();
+    let mut style_prompt: Option<String> = None;
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -59,6 +185,103 @@ async fn main() -> Result<()> {
                 });
                 api_key_arg = Some(k);
             }
+            "--strictness" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: --strictness requires a value");
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                strictness = Strictness::parse(&v).unwrap_or_else(|| {
+                    eprintln!(
+                        "error: unknown strictness '{}' (expected strict or warn)",
+                        v
+                    );
+                    std::process::exit(2);
+                });
+            }
+            "--strict" => {
+                strictness = Strictness::Strict;
+            }
+            "--table-summary-rows" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: --table-summary-rows requires a value");
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                table_summary_rows = Some(v.parse().unwrap_or_else(|_| {
+                    eprintln!("error: --table-summary-rows expects a number, got '{}'", v);
+                    std::process::exit(2);
+                }));
+            }
+            "--footnotes" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: --footnotes requires a value");
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                footnote_policy = FootnotePolicy::parse(&v).unwrap_or_else(|| {
+                    eprintln!(
+                        "error: unknown footnote policy '{}' (expected inline, section-end or drop)",
+                        v
+                    );
+                    std::process::exit(2);
+                });
+            }
+            "--lexicon" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: --lexicon requires a value");
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                lexicon_path = Some(PathBuf::from(v));
+            }
+            "--write-ssml" => {
+                write_ssml = true;
+            }
+            "--no-image-descriptions" => {
+                describe_images = false;
+            }
+            "--no-stream" => {
+                streaming = false;
+            }
+            "--locale" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: --locale requires a value");
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                locale = Locale::parse(&v).unwrap_or_else(|| {
+                    eprintln!(
+                        "error: unsupported locale '{}' (expected en-US or en-GB)",
+                        v
+                    );
+                    std::process::exit(2);
+                });
+            }
+            "--style-prompt" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: --style-prompt requires a value");
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                style_prompt = Some(v);
+            }
+            "--heading-pause-before" | "--heading-pause-after" => {
+                let v = args.next().unwrap_or_else(|| {
+                    eprintln!("error: {} requires a value", arg);
+                    print_help(&program);
+                    std::process::exit(2);
+                });
+                let ms: u32 = v.parse().unwrap_or_else(|_| {
+                    eprintln!("error: {} expects milliseconds, got '{}'", arg, v);
+                    std::process::exit(2);
+                });
+                if arg == "--heading-pause-before" {
+                    headings.pause_before_ms = ms;
+                } else {
+                    headings.pause_after_ms = ms;
+                }
+            }
             other => {
                 file_args.push(PathBuf::from(other));
             }
@@ -77,7 +300,14 @@ async fn main() -> Result<()> {
         }),
     };
 
-    let client = GeminiClient::new(api_key)?;
+    let client = GeminiClient::new(api_key)?
+        .with_style_prompt(style_prompt)
+        .with_streaming(streaming);
+
+    let lexicon = match &lexicon_path {
+        Some(p) => Lexicon::load(p)?,
+        None => Lexicon::rust_book(),
+    };
 
     // Ensure audio output directory exists
     let audio_dir = Path::new("audio");
@@ -108,10 +338,34 @@ async fn main() -> Result<()> {
         paths.len(),
         voice_name
     );
+    let opts = ChapterOptions {
+        voice_name,
+        strictness,
+        table_summary_rows,
+        footnote_policy,
+        lexicon,
+        write_ssml,
+        describe_images,
+        locale,
+        headings,
+    };
+    // A failed chapter (e.g. a missing include in strict mode) is reported and skipped;
+    // the run carries on with the rest and exits non-zero at the end
+    let mut failed: Vec<&PathBuf> = Vec::new();
     for (i, path) in paths.iter().enumerate() {
         println!("[{} / {}] Starting {}", i + 1, paths.len(), path.display());
         let t0 = Instant::now();
-        process_markdown_file(&client, path, audio_dir, &voice_name).await?;
+        if let Err(e) = process_markdown_file(&client, path, audio_dir, &opts).await {
+            eprintln!(
+                "error: [{} / {}] {} failed: {:#}",
+                i + 1,
+                paths.len(),
+                path.display(),
+                e
+            );
+            failed.push(path);
+            continue;
+        }
         println!(
             "[{} / {}] Finished {} in {:?}",
             i + 1,
@@ -121,14 +375,37 @@ async fn main() -> Result<()> {
         );
     }
 
+    if !failed.is_empty() {
+        for path in &failed {
+            eprintln!("failed: {}", path.display());
+        }
+        return Err(anyhow!(
+            "{} of {} chapter(s) failed",
+            failed.len(),
+            paths.len()
+        ));
+    }
     Ok(())
 }
 
+/// Per-run settings shared by every chapter.
+struct ChapterOptions {
+    voice_name: String,
+    strictness: Strictness,
+    table_summary_rows: Option<usize>,
+    footnote_policy: FootnotePolicy,
+    lexicon: Lexicon,
+    write_ssml: bool,
+    describe_images: bool,
+    locale: Locale,
+    headings: HeadingOptions,
+}
+
 async fn process_markdown_file(
     client: &GeminiClient,
     path: &Path,
     audio_dir: &Path,
-    voice_name: &str,
+    opts: &ChapterOptions,
 ) -> Result<()> {
     let original = fs::read_to_string(path)
         .with_context(|| format!("failed to read file {}", path.display()))?;
@@ -137,19 +414,162 @@ async fn process_markdown_file(
         path.display(),
         original.chars().count()
     );
+    // Pull out chapter metadata ({{#title}}) so the directive is never narrated
+    let (original, meta) = extract_chapter_meta(&original);
+    if let Some(title) = &meta.title {
+        println!("Chapter title: {}", title);
+    }
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("invalid file name: {}", path.display()))?;
+
     // Expand any mdBook-style includes before code summarization
-    let expanded = expand_includes(path, &original)?;
+    let mut diagnostics = Diagnostics::default();
+    let expanded = expand_includes(path, &original, opts.strictness, &mut diagnostics)?;
+    let expanded = mark_ferris_warnings(&expanded);
 
     let (transformed, summarized_blocks) =
-        replace_code_blocks_with_summaries(client, &expanded).await?;
+        replace_code_blocks_with_summaries(client, &expanded, opts.strictness, &mut diagnostics)
+            .await?;
     println!(
         "Summarized {} code block(s) in {}",
         summarized_blocks,
         path.display()
     );
 
-    // Split content into <= 3000-char chunks on paragraph boundaries
-    let tts_text = sanitize_markdown_for_tts(&transformed);
+    let (transformed, narrated_tables) = narrate_tables(
+        client,
+        &transformed,
+        opts.table_summary_rows,
+        opts.strictness,
+        &mut diagnostics,
+    )
+    .await?;
+    if narrated_tables > 0 {
+        println!(
+            "Converted {} table(s) to narration in {}",
+            narrated_tables,
+            path.display()
+        );
+    }
+    let transformed = if opts.describe_images {
+        let cache_dir = audio_dir.join("image-descriptions");
+        let (described, count) = describe_images(
+            client,
+            path,
+            &transformed,
+            &cache_dir,
+            opts.strictness,
+            &mut diagnostics,
+        )
+        .await?;
+        if count > 0 {
+            println!("Described {} image(s) in {}", count, path.display());
+        }
+        described
+    } else {
+        transformed
+    };
+    let transformed = narrate_callouts(&transformed);
+    let transformed = resolve_footnotes(&transformed, opts.footnote_policy);
+
+    let report_path = audio_dir.join(format!("{}.diagnostics.txt", stem));
+    if !diagnostics.is_empty() {
+        diagnostics.write_report(&report_path, path)?;
+        println!(
+            "Wrote {} warning(s) to {}",
+            diagnostics.warnings.len(),
+            report_path.display()
+        );
+    } else if report_path.exists() {
+        // Don't leave a report from an earlier run next to a clean chapter
+        fs::remove_file(&report_path)
+            .with_context(|| format!("failed to remove {}", report_path.display()))?;
+    }
+
+    // Announce headings ("Chapter 3, section 2: ...") using the SUMMARY numbering
+    let number =
+        Summary::for_chapter(path).and_then(|s| s.find(path).and_then(|e| e.number.clone()));
+    let announced = announce_headings(&transformed, number.as_deref());
+
+    if opts.write_ssml {
+        let ssml_path = audio_dir.join(format!("{}.ssml", stem));
+        let doc = wrap_ssml_document(&markdown_to_ssml(&announced, &opts.lexicon, &opts.headings));
+        fs::write(&ssml_path, doc)
+            .with_context(|| format!("failed to write SSML {}", ssml_path.display()))?;
+        println!("Wrote SSML to {}", ssml_path.display());
+    }
+
+    // Build the provider's input format (SSML where supported, plain text otherwise) per
+    // section, with the silence to insert before each piece. SSML carries its own breaks.
+    // Plain text stays in its written form here; the lexicon and number reading are applied
+    // per chunk piece, so captions and timings can keep the written text.
+    let text_format = client.text_format();
+    let sections = split_sections(&announced);
+    let sources = source_sections(&sections, &original);
+    let mut segments: Vec<Chunk> = Vec::new();
+    for (section, source) in sections.iter().zip(sources) {
+        let text = match text_format {
+            TextFormat::Ssml => markdown_to_ssml(&section.markdown, &opts.lexicon, &opts.headings),
+            TextFormat::Plain => sanitize_markdown_for_tts(&section.markdown),
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let plain_heading = text_format == TextFormat::Plain && section.heading.is_some();
+        let pause_before = if plain_heading && !segments.is_empty() {
+            opts.headings.pause_before_ms
+        } else {
+            0
+        };
+        let segment = |text: &str, pause_before_ms: u32| Chunk {
+            heading: source
+                .as_ref()
+                .map_or(section.heading.clone(), |s| s.heading.clone()),
+            source_lines: source.as_ref().map(|s| s.lines),
+            pause_before_ms,
+            text: text.to_string(),
+            written: text.to_string(),
+        };
+        // A pause after the heading needs the heading as its own TTS request
+        if plain_heading
+            && opts.headings.pause_after_ms > 0
+            && let Some((head, rest)) = text.split_once('\n')
+            && !rest.trim().is_empty()
+        {
+            segments.push(segment(head, pause_before));
+            segments.push(segment(rest, opts.headings.pause_after_ms));
+            continue;
+        }
+        segments.push(segment(&text, pause_before));
+    }
+    if let Some(title) = &meta.title
+        && let Some(first) = segments.first_mut()
+    {
+        let spoken_title = match text_format {
+            TextFormat::Ssml => escape_xml(title),
+            TextFormat::Plain => title.clone(),
+        };
+        first.text = prepend_title_announcement(&first.text, &spoken_title);
+    }
+    // Split each section into chunks within the provider's budget (minus the style prompt
+    // and SSML wrapper), preferring paragraph and sentence ends
+    let overhead = match text_format {
+        TextFormat::Ssml => format!("{}{}", client.prompt_prefix(), wrap_ssml_document("")),
+        TextFormat::Plain => client.prompt_prefix(),
+    };
+    let budget = client.input_limits().chunk_budget(&overhead)?;
+    let normalizer = Normalizer::new(opts.locale);
+    let chunks = chunk_sections(segments, budget, |written| match text_format {
+        TextFormat::Ssml => written.to_string(),
+        TextFormat::Plain => normalizer.apply(&opts.lexicon.apply(written)),
+    });
+    let tts_text = chunks
+        .iter()
+        .map(|s| s.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
     println!(
         "Sanitized text for TTS (links/headers/lists/html/code fences): {} -> {} chars",
         transformed.chars().count(),
@@ -165,60 +585,102 @@ async fn process_markdown_file(
         .ok_or_else(|| anyhow!("invalid file name: {}", path.display()))?;
     let mut tmp_md = File::create(tmp_dir.with_file_name(file_name))?;
     tmp_md.write_all(tts_text.as_bytes())?;
-    let chunks = split_into_chunks_by_paragraph(&tts_text, 3000);
     println!(
-        "Chunked content into {} piece(s) (<=3000 chars each)",
-        chunks.len()
+        "Chunked content into {} piece(s) (<={} {} each)",
+        chunks.len(),
+        budget.max,
+        budget.measure.name()
     );
+    let map_path = audio_dir.join(format!("{}.chunks.json", stem));
+    write_chunk_map(&map_path, &chunks)?;
 
-    let stem = path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .ok_or_else(|| anyhow!("invalid file name: {}", path.display()))?;
-
-    let mut parts: Vec<(Vec<u8>, String)> = Vec::new();
+    // Parts are appended to the output file as they arrive
+    let mut writer: Option<ChapterAudioWriter> = None;
+    // Where each chunk starts in the merged audio and how long it lasts; None once a part's
+    // duration can't be read (non-WAV audio)
+    let mut elapsed: Option<f64> = Some(0.0);
+    let mut timings: Vec<SentenceTiming> = Vec::new();
     for (i, chunk) in chunks.iter().enumerate() {
         println!(
             "{} | TTS part {:02}/{:02}: {} chars...",
             now_ts(),
             i + 1,
             chunks.len(),
-            chunk.chars().count()
+            chunk.text.chars().count()
         );
         let t0 = Instant::now();
 
+        let tts_input = match text_format {
+            TextFormat::Ssml => wrap_ssml_document(&chunk.text),
+            TextFormat::Plain => chunk.text.clone(),
+        };
+
+        if chunk.pause_before_ms > 0
+            && let Some(w) = writer.as_mut()
+            && w.is_wav()
+        {
+            match w.append_silence(chunk.pause_before_ms) {
+                Ok(d) => elapsed = elapsed.map(|a| a + d),
+                Err(e) => eprintln!("warn: could not insert heading pause: {}", e),
+            }
+        }
+
+        // Audio is written to the chapter file as it arrives; a mostly silent part is cut
+        // back off before regenerating
         let silence_threshold: f32 = 0.6; // 60% or more near-silence is considered bad
         let max_regen_attempts: usize = 2; // total attempts = 1 + max_regen_attempts
         let mut attempt = 0usize;
-        let (audio_bytes, mime_type) = loop {
-            let (bytes, mime) =
-                client
-                    .tts_generate(chunk, voice_name)
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "TTS generation failed for {} (part {})",
-                            path.display(),
-                            i + 1
-                        )
-                    })?;
-
-            let ratio_opt = try_silence_ratio_from_mime(&bytes, &mime);
-            if let Some(ratio) = ratio_opt {
-                if ratio >= silence_threshold && attempt < max_regen_attempts {
-                    eprintln!(
-                        "warn: TTS part {:02}/{:02} mostly silent ({:.0}%); regenerating (attempt {}/{})",
-                        i + 1,
-                        chunks.len(),
-                        ratio * 100.0,
-                        attempt + 1,
-                        max_regen_attempts
-                    );
-                    attempt += 1;
-                    continue;
-                }
+        let (mime_type, stats) = loop {
+            if let Some(w) = writer.as_mut() {
+                w.begin_part();
+            }
+            let mime = client
+                .tts_generate(&tts_input, &opts.voice_name, |bytes, mime| {
+                    match writer.as_mut() {
+                        Some(w) => w.write_part(bytes),
+                        None => {
+                            writer = Some(ChapterAudioWriter::create(
+                                audio_dir,
+                                stem,
+                                bytes,
+                                mime,
+                                meta.title.as_deref(),
+                            )?);
+                            Ok(())
+                        }
+                    }
+                })
+                .await
+                .with_context(|| {
+                    format!(
+                        "TTS generation failed for {} (part {})",
+                        path.display(),
+                        i + 1
+                    )
+                })?;
+            let w = writer
+                .as_mut()
+                .ok_or_else(|| anyhow!("no audio written for part {}", i + 1))?;
+            let stats = w.end_part();
+
+            if let Some(ratio) = stats.silence_ratio
+                && ratio >= silence_threshold
+                && attempt < max_regen_attempts
+            {
+                eprintln!(
+                    "warn: TTS part {:02}/{:02} mostly silent ({:.0}%); regenerating (attempt {}/{})",
+                    i + 1,
+                    chunks.len(),
+                    ratio * 100.0,
+                    attempt + 1,
+                    max_regen_attempts
+                );
+                w.discard_part()
+                    .with_context(|| format!("failed to discard audio for part {}", i + 1))?;
+                attempt += 1;
+                continue;
             }
-            break (bytes, mime);
+            break (mime, stats);
         };
 
         println!(
@@ -227,54 +689,71 @@ async fn process_markdown_file(
             i + 1,
             chunks.len(),
             mime_type,
-            audio_bytes.len(),
+            stats.bytes,
             t0.elapsed()
         );
-        parts.push((audio_bytes, mime_type));
+        if let Some((start, d)) = elapsed.zip(stats.duration) {
+            let caption = match text_format {
+                TextFormat::Ssml => ssml_to_text(&chunk.text),
+                TextFormat::Plain => chunk.written.clone(),
+            };
+            timings.extend(align_sentences(
+                i,
+                &split_sentences(&caption),
+                stats.envelope.as_deref(),
+                start,
+                d,
+            ));
+        }
+        elapsed = elapsed.zip(stats.duration).map(|(a, d)| a + d);
     }
 
-    // Determine a single mime type (all parts should match); fall back to first.
-    let mime = parts
-        .get(0)
-        .map(|(_, m)| m.clone())
-        .unwrap_or_else(|| "application/octet-stream".to_string());
-    let ext = guess_audio_extension(&mime);
-
-    let merged: Vec<u8> = if parts.len() == 1 {
-        parts.remove(0).0
-    } else if mime.contains("mpeg") || mime.contains("mp3") {
-        println!("Merging {} MP3 parts via concatenation", parts.len());
-        merge_mp3(&parts.iter().map(|(b, _)| b.as_slice()).collect::<Vec<_>>())
-    } else if mime.contains("wav") || mime.contains("x-wav") || mime.contains("pcm") {
-        println!("Merging {} WAV parts with header rewrite", parts.len());
-        match try_merge_wav(&parts.iter().map(|(b, _)| b.as_slice()).collect::<Vec<_>>()) {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                eprintln!(
-                    "warn: WAV merge failed ({}). Falling back to naive concat.",
-                    e
-                );
-                merge_concat(&parts.iter().map(|(b, _)| b.as_slice()).collect::<Vec<_>>())
-            }
-        }
+    let Some(writer) = writer else {
+        eprintln!("warn: no audio generated for {}", path.display());
+        return Ok(());
+    };
+    let mime = writer.mime().to_string();
+    let part_count = writer.parts();
+    let (out_path, size) = writer.finish()?;
+
+    // Sentence timing map and captions built from it
+    if elapsed.is_some() {
+        let map_path = audio_dir.join(format!("{}.timings.json", stem));
+        write_timing_map(&map_path, &timings)?;
+        let aligned = timings.iter().filter(|t| t.aligned).count();
+        println!(
+            "Aligned {} of {} sentence(s); wrote {}",
+            aligned,
+            timings.len(),
+            map_path.display()
+        );
+        let cues: Vec<Cue> = timings
+            .iter()
+            .map(|t| Cue {
+                start: t.start,
+                end: t.end,
+                text: t.text.clone(),
+            })
+            .collect();
+        write_subtitles(audio_dir, stem, &cues)?;
+        println!(
+            "Wrote {} caption cue(s) to {}.srt / .vtt",
+            cues.len(),
+            audio_dir.join(stem).display()
+        );
     } else {
         eprintln!(
-            "warn: unsupported mime '{}' for merging; concatenating raw bytes (may not play correctly)",
+            "warn: part durations unknown for '{}' audio; skipping timings and captions",
             mime
         );
-        merge_concat(&parts.iter().map(|(b, _)| b.as_slice()).collect::<Vec<_>>())
-    };
-
-    let out_path = audio_dir.join(format!("{}{}", stem, ext));
-    fs::write(&out_path, &merged)
-        .with_context(|| format!("failed to write audio file {}", out_path.display()))?;
+    }
 
     println!(
-        "Processed {} => {} ({} bytes from {} chunks)",
+        "Processed {} => {} ({} bytes from {} parts)",
         path.display(),
         out_path.display(),
-        merged.len(),
-        parts.len()
+        size,
+        part_count
     );
 
     Ok(())
@@ -282,8 +761,8 @@ async fn process_markdown_file(
 
 fn print_help(program: &str) {
     println!(
-        "Usage: {program} [OPTIONS] [MARKDOWN_FILE]\n\n\
-Options:\n  -v, --voice <NAME>   Choose a voice (default: Zephyr)\n  -k, --api-key <KEY>  Provide Gemini API key (overrides GEMINI_API_KEY)\n      --list-voices     List available voices and exit\n  -h, --help           Show this help and exit\n\n\
+        "Usage: {program} [OPTIONS] [MARKDOWN_FILE]\n       {program} mdbook [supports <RENDERER>]   (mdBook preprocessor)\n       {program} epub [OUTPUT]   (EPUB 3 with read-along audio; default audio/book.epub)\n       {program} podcast --base-url <URL> [--title <T>] [--author <A>] [--description <D>] [--image <URL>]   (RSS feed + index page in audio/)\n       {program} serve [--bind <ADDR>] [--port <N>]   (serve audio/, the feed and a web player over HTTP; default 0.0.0.0:8080)\n\n\
+Options:\n  -v, --voice <NAME>   Choose a voice (default: Zephyr)\n  -k, --api-key <KEY>  Provide Gemini API key (overrides GEMINI_API_KEY)\n      --strictness <MODE>  strict: fail the chapter on include/summary errors; warn (default): skip and report\n      --strict         Shorthand for --strictness strict\n      --table-summary-rows <N>  Summarize tables with more than N rows via the LLM instead of reading each row\n      --lexicon <FILE>  Pronunciation lexicon (TOML) replacing the bundled lexicon/rust-book.toml\n      --locale <LOCALE>  Number/symbol reading rules: en-US (default) or en-GB\n      --style-prompt <TEXT>  Style instruction sent before each TTS input (e.g. \"Read aloud in a calm tone\")\n      --heading-pause-before <MS>  Silence before each heading (default: 1200)\n      --heading-pause-after <MS>   Silence after each heading (default: 0; >0 sends headings as separate TTS requests)\n      --no-image-descriptions  Read images as their alt text instead of describing them with the model\n      --no-stream      Request each chunk with generateContent instead of streaming it (streamGenerateContent)\n      --write-ssml     Also write audio/<chapter>.ssml (SSML rendering of the chapter)\n      --footnotes <POLICY>  inline (default): read after the citing sentence; section-end: read before the next heading; drop\n      --list-voices     List available voices and exit\n  -h, --help           Show this help and exit\n\n\
 Args:\n  MARKDOWN_FILE        Optional single markdown file. If omitted, processes all book/src/*.md\n\n\
 Examples:\n  {program} --voice Zephyr\n  {program} --voice Leda book/src/ch08-02-strings.md\n  {program} --api-key YOUR_KEY --voice Zephyr book/src/ch06-02-match.md\n  {program} --list-voices\n"
     );
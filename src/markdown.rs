@@ -4,63 +4,144 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
+use crate::diagnostics::{Diagnostics, Strictness};
 use crate::tts::GeminiClient;
 
+/// Chapter-level metadata collected from mdBook directives.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChapterMeta {
+    /// Page title from `{{#title ...}}`, if the chapter sets one.
+    pub title: Option<String>,
+}
+
+/// Remove `{{#title ...}}` directives from the Markdown and return them as metadata.
+/// When several are present the last one wins, matching mdBook.
+pub fn extract_chapter_meta(input: &str) -> (String, ChapterMeta) {
+    let re = Regex::new(r"\{\{\s*#title\s+([^}]*)\}\}").expect("valid title regex");
+    let mut meta = ChapterMeta::default();
+    for caps in re.captures_iter(input) {
+        let title = caps.get(1).map(|m| m.as_str().trim()).unwrap_or("");
+        if !title.is_empty() {
+            meta.title = Some(title.to_string());
+        }
+    }
+    let stripped = re.replace_all(input, "").into_owned();
+    (stripped, meta)
+}
+
+/// Prepend a spoken title to the narration unless its first line already carries it.
+pub fn prepend_title_announcement(text: &str, title: &str) -> String {
+    let first_line = text.trim_start().lines().next().unwrap_or("");
+    if first_line.contains(title) {
+        text.to_string()
+    } else {
+        format!("{title}.\n{text}")
+    }
+}
+
 /// Expand mdBook include directives in the given Markdown text.
 /// - `{{#include path}}` is replaced with the file contents at `path` (relative to the MD file)
 /// - `{{#rustdoc_include path[:tag]}}` is replaced with contents of `path`, optionally extracting
 ///   the region between lines `// ANCHOR: tag` and `// ANCHOR_END: tag`. Anchor comment lines
 ///   themselves are removed.
-pub fn expand_includes(markdown_path: &Path, input: &str) -> Result<String> {
-    let re = Regex::new(r"\{\{\s*#(rustdoc_include|include)\s+([^}]+)\}\}")
+/// - `{{#playground path [attrs]}}` is replaced with the file contents wrapped in a rust fence,
+///   so it gets summarized like any other listing.
+///
+/// Unreadable files and missing anchors are handled according to `strictness`: either the
+/// whole call fails, or the directive expands to nothing and a warning lands in `diagnostics`.
+pub fn expand_includes(
+    markdown_path: &Path,
+    input: &str,
+    strictness: Strictness,
+    diagnostics: &mut Diagnostics,
+) -> Result<String> {
+    let re = Regex::new(r"\{\{\s*#(rustdoc_include|include|playground)\s+([^}]+)\}\}")
         .expect("valid include regex");
 
-    // Use replace_all to handle multiple directives possibly on the same line.
-    let result = re
-        .replace_all(input, |caps: &regex::Captures| {
-            let kind = caps.get(1).map(|m| m.as_str()).unwrap_or("");
-            let arg = caps.get(2).map(|m| m.as_str().trim()).unwrap_or("");
-
-            let (path_str, tag_opt) = if kind == "rustdoc_include" {
-                // Split on the last ':' to allow optional region tag suffix
-                if let Some(idx) = arg.rfind(':') {
-                    (&arg[..idx], Some(arg[idx + 1..].trim()))
-                } else {
-                    (arg, None)
-                }
+    // Walk the matches manually (rather than replace_all) so failures can propagate.
+    let mut result = String::with_capacity(input.len());
+    let mut last = 0usize;
+    for caps in re.captures_iter(input) {
+        let whole = caps.get(0).expect("match 0 always present");
+        result.push_str(&input[last..whole.start()]);
+        last = whole.end();
+
+        let kind = caps.get(1).map(|m| m.as_str()).unwrap_or("");
+        let arg = caps.get(2).map(|m| m.as_str().trim()).unwrap_or("");
+
+        // Playground directives may carry attributes after the path (e.g. `editable`)
+        let (arg, attrs) = if kind == "playground" {
+            let mut it = arg.split_whitespace();
+            let p = it.next().unwrap_or("");
+            (p, it.collect::<Vec<_>>())
+        } else {
+            (arg, Vec::new())
+        };
+
+        let (path_str, tag_opt) = if kind == "rustdoc_include" {
+            // Split on the last ':' to allow optional region tag suffix
+            if let Some(idx) = arg.rfind(':') {
+                (&arg[..idx], Some(arg[idx + 1..].trim()))
             } else {
                 (arg, None)
-            };
-
-            // Resolve path relative to the Markdown file's directory
-            let base = markdown_path.parent().unwrap_or_else(|| Path::new("."));
-            let target_path = normalize_path(base, path_str);
-
-            match fs::read_to_string(&target_path) {
-                Ok(file_text) => {
-                    if kind == "rustdoc_include" {
-                        // Extract region if requested and strip anchor comment lines
-                        let included = match tag_opt {
-                            Some(tag) if !tag.is_empty() => {
-                                extract_anchored_region(&file_text, tag)
-                                    .unwrap_or_else(|_| String::new())
-                            }
-                            _ => strip_anchor_comment_lines(&file_text),
-                        };
-                        included
-                    } else {
-                        file_text
-                    }
-                }
-                Err(e) => format!(
-                    "[include error: could not read {} (resolved from {}): {}]",
-                    target_path.display(),
-                    path_str,
-                    e
-                ),
             }
-        })
-        .into_owned();
+        } else {
+            (arg, None)
+        };
+
+        // Resolve path relative to the Markdown file's directory
+        let base = markdown_path.parent().unwrap_or_else(|| Path::new("."));
+        let target_path = normalize_path(base, path_str);
+
+        let file_text = match fs::read_to_string(&target_path) {
+            Ok(t) => t,
+            Err(e) => {
+                diagnostics.report(
+                    strictness,
+                    format!(
+                        "{}: could not read include {} (resolved from {}): {}",
+                        markdown_path.display(),
+                        target_path.display(),
+                        path_str,
+                        e
+                    ),
+                )?;
+                continue;
+            }
+        };
+
+        if kind == "rustdoc_include" {
+            // Extract region if requested and strip anchor comment lines
+            match tag_opt {
+                Some(tag) if !tag.is_empty() => match extract_anchored_region(&file_text, tag) {
+                    Ok(region) => result.push_str(&region),
+                    Err(e) => diagnostics.report(
+                        strictness,
+                        format!(
+                            "{}: {} in {}",
+                            markdown_path.display(),
+                            e,
+                            target_path.display()
+                        ),
+                    )?,
+                },
+                _ => result.push_str(&strip_anchor_comment_lines(&file_text)),
+            }
+        } else if kind == "playground" {
+            let info = std::iter::once("rust")
+                .chain(attrs.iter().copied())
+                .collect::<Vec<_>>()
+                .join(",");
+            result.push_str(&format!(
+                "```{}\n{}\n```",
+                info,
+                strip_anchor_comment_lines(&file_text).trim_end()
+            ));
+        } else {
+            result.push_str(&file_text);
+        }
+    }
+    result.push_str(&input[last..]);
 
     Ok(result)
 }
@@ -116,14 +197,16 @@ fn extract_anchored_region(input: &str, tag: &str) -> Result<String> {
 pub async fn replace_code_blocks_with_summaries(
     client: &GeminiClient,
     input: &str,
+    strictness: Strictness,
+    diagnostics: &mut Diagnostics,
 ) -> Result<(String, usize)> {
     let mut out = String::with_capacity(input.len());
-    let mut lines = input.lines();
+    let lines = input.lines();
     let mut in_block = false;
     let mut code_acc: Vec<String> = Vec::new();
     let mut count_blocks = 0usize;
 
-    while let Some(line) = lines.next() {
+    for line in lines {
         if !in_block {
             if is_fence_open(line) {
                 in_block = true;
@@ -143,10 +226,16 @@ pub async fn replace_code_blocks_with_summaries(
                 code_text.chars().count()
             );
             let t0 = Instant::now();
-            let summary = client
-                .summarize_code_block(&code_text)
-                .await
-                .unwrap_or_else(|e| format!("[summary failed: {e}]"));
+            let summary = match client.summarize_code_block(&code_text).await {
+                Ok(s) => s,
+                Err(e) => {
+                    diagnostics.report(
+                        strictness,
+                        format!("summary of code block #{} failed: {}", count_blocks, e),
+                    )?;
+                    String::new()
+                }
+            };
 
             let summary_trimmed = collapse_multiple_newlines(summary.trim());
             println!(
@@ -178,10 +267,16 @@ pub async fn replace_code_blocks_with_summaries(
             code_text.chars().count()
         );
         let t0 = Instant::now();
-        let summary = client
-            .summarize_code_block(&code_text)
-            .await
-            .unwrap_or_else(|e| format!("[summary failed: {e}]"));
+        let summary = match client.summarize_code_block(&code_text).await {
+            Ok(s) => s,
+            Err(e) => {
+                diagnostics.report(
+                    strictness,
+                    format!("summary of code block #{} failed: {}", count_blocks, e),
+                )?;
+                String::new()
+            }
+        };
         let summary_trimmed = collapse_multiple_newlines(summary.trim());
         println!(
             "Summary #{} done ({} chars) in {:?}",
@@ -207,64 +302,6 @@ fn is_fence_close(line: &str) -> bool {
     trimmed.starts_with("```")
 }
 
-pub fn split_into_chunks_by_paragraph(input: &str, max_chars: usize) -> Vec<String> {
-    if input.chars().count() <= max_chars {
-        return vec![input.to_string()];
-    }
-
-    let mut chunks = Vec::new();
-    let mut start = 0usize;
-    let chars: Vec<char> = input.chars().collect();
-    let total = chars.len();
-
-    while start < total {
-        let remaining = total - start;
-        let take = remaining.min(max_chars);
-        let mut end = start + take;
-
-        if end < total {
-            let mut last_para_break: Option<usize> = None;
-            let mut i = start;
-            while i < end {
-                if chars[i] == '\n' {
-                    let mut j = i + 1;
-                    let mut nl_count = 1;
-                    while j < end && chars[j] == '\n' {
-                        nl_count += 1;
-                        j += 1;
-                    }
-                    if nl_count >= 1 {
-                        last_para_break = Some(i);
-                    }
-                    i = j;
-                    continue;
-                }
-                i += 1;
-            }
-
-            if let Some(bp) = last_para_break {
-                end = bp + 1;
-            } else {
-                let mut last_sentence_end: Option<usize> = None;
-                for k in start..end {
-                    if matches!(chars[k], '.' | '!' | '?') {
-                        last_sentence_end = Some(k);
-                    }
-                }
-                if let Some(se) = last_sentence_end {
-                    end = se + 1;
-                }
-            }
-        }
-
-        let slice: String = chars[start..end].iter().collect();
-        chunks.push(slice);
-        start = end;
-    }
-
-    chunks
-}
-
 fn remove_links_for_tts(input: &str) -> String {
     // 1) Convert Markdown images to their alt text (drop the image itself)
     //    Examples: ![Alt text](url) -> Alt text,  ![Alt][id] -> Alt
@@ -298,26 +335,76 @@ fn remove_links_for_tts(input: &str) -> String {
 
     // 6) Remove bare URLs http(s)://...
     let re_bare = Regex::new(r"https?://\S+").unwrap();
-    let tmp = re_bare.replace_all(&tmp, "").into_owned();
-
-    tmp
+    re_bare.replace_all(&tmp, "").into_owned()
 }
 
 pub fn sanitize_markdown_for_tts(input: &str) -> String {
+    let mut text = strip_markup_for_speech(input);
+
+    // Remove backticks (inline code markers)
+    text = text.replace('`', "");
+
+    // Strip heading #'s, blockquote '>'s, and list markers
+    let re_heading = Regex::new(r"^\s*#{1,6}\s*").unwrap();
+    let re_blockquote = Regex::new(r"^\s*>+\s*").unwrap();
+    let re_bullet = Regex::new(r"^\s*[-*+]\s+").unwrap();
+    let re_numbered = Regex::new(r"^\s*\d+[\.)]\s+").unwrap();
+
+    let mut out_lines = Vec::new();
+    for line in text.lines() {
+        let mut l = line.to_string();
+        l = re_heading.replace(&l, "").into_owned();
+        l = re_blockquote.replace(&l, "").into_owned();
+        l = re_bullet.replace(&l, "").into_owned();
+        l = re_numbered.replace(&l, "").into_owned();
+        out_lines.push(l);
+    }
+    let mut joined = out_lines.join("\n");
+
+    // Keep one blank line between paragraphs so the chunker can still break there
+    let re_multi_blank = Regex::new(r"\n[ \t]*(\n[ \t]*)+").unwrap();
+    joined = re_multi_blank.replace_all(&joined, "\n\n").into_owned();
+
+    joined.trim().to_string()
+}
+
+/// Strip the parts of the Markdown that should never be spoken: links and URLs, code fences,
+/// HTML tags and comments. `<Listing>` openers become announcements and images their alt text.
+/// Headings, list markers, emphasis and inline code are left for the caller to interpret.
+pub(crate) fn strip_markup_for_speech(input: &str) -> String {
     // First, remove links
     let mut text = remove_links_for_tts(input);
 
-    // Drop lines starting with <Listing or </Listing and code fences
-    let mut lines = Vec::new();
+    // Turn <Listing ...> openers into a spoken introduction; drop </Listing> and code fences
+    let mut lines: Vec<String> = Vec::new();
+    let mut pending_listing: Option<String> = None;
     for line in text.lines() {
         let t = line.trim_start();
-        if t.starts_with("<Listing") || t.starts_with("</Listing") {
+        // A <Listing> tag may wrap over several lines; gather until it closes
+        if let Some(acc) = pending_listing.as_mut() {
+            acc.push(' ');
+            acc.push_str(t);
+            if t.contains('>') {
+                lines.extend(listing_announcement(acc));
+                pending_listing = None;
+            }
+            continue;
+        }
+        if t.starts_with("<Listing") {
+            if t.contains('>') {
+                lines.extend(listing_announcement(t));
+            } else {
+                pending_listing = Some(t.to_string());
+            }
+            continue;
+        }
+        if t.starts_with("</Listing") {
             continue;
         }
         if t.starts_with("```") {
             continue;
         }
-        lines.push(line);
+        lines.push(line.to_string());
     }
     text = lines.join("\n");
 
@@ -339,38 +426,51 @@ pub fn sanitize_markdown_for_tts(input: &str) -> String {
     let re_comment = Regex::new(r"(?s)<!--.*?-->").unwrap();
     text = re_comment.replace_all(&text, "").into_owned();
     let re_tags = Regex::new(r"</?[^>]+>").unwrap();
-    text = re_tags.replace_all(&text, "").into_owned();
-
-    // Replace &str with "ref estr" and `str` with estr
-    text = text.replace("&str", "ref estr").replace("`str`", "estr");
-
-    // Remove backticks (inline code markers)
-    text = text.replace('`', "");
-
-    // Strip heading #'s, blockquote '>'s, and list markers
-    let re_heading = Regex::new(r"^\s*#{1,6}\s*").unwrap();
-    let re_blockquote = Regex::new(r"^\s*>+\s*").unwrap();
-    let re_bullet = Regex::new(r"^\s*[-*+]\s+").unwrap();
-    let re_numbered = Regex::new(r"^\s*\d+[\.)]\s+").unwrap();
+    re_tags.replace_all(&text, "").into_owned()
+}
 
-    let mut out_lines = Vec::new();
-    for line in text.lines() {
-        let mut l = line.to_string();
-        l = re_heading.replace(&l, "").into_owned();
-        l = re_blockquote.replace(&l, "").into_owned();
-        l = re_bullet.replace(&l, "").into_owned();
-        l = re_numbered.replace(&l, "").into_owned();
-        out_lines.push(l);
+/// Build the spoken introduction for a `<Listing number="..." file-name="..." caption="...">`
+/// tag, e.g. "Listing 3-1, in source main.rs: A function that returns a value."
+/// Returns None when the tag carries none of those attributes.
+fn listing_announcement(tag: &str) -> Option<String> {
+    let re_attr = Regex::new(r#"([\w-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let mut number = None;
+    let mut file_name = None;
+    let mut caption = None;
+    for caps in re_attr.captures_iter(tag) {
+        let value = caps
+            .get(2)
+            .or_else(|| caps.get(3))
+            .map(|m| m.as_str().trim().to_string())
+            .filter(|v| !v.is_empty());
+        match &caps[1] {
+            "number" => number = value,
+            "file-name" => file_name = value,
+            "caption" => caption = value,
+            _ => {}
+        }
+    }
+    if number.is_none() && file_name.is_none() && caption.is_none() {
+        return None;
     }
-    let mut joined = out_lines.join("\n");
-
-    // Replace any 'scr/' with 'source/' as requested
-    joined = joined.replace("scr/", "source/");
-
-    let re_multi_blank = Regex::new(r"\n{2,}").unwrap();
-    joined = re_multi_blank.replace_all(&joined, "\n").into_owned();
 
-    joined.trim().to_string()
+    let mut out = match number {
+        Some(n) => format!("Listing {}", n),
+        None => "Listing".to_string(),
+    };
+    if let Some(f) = file_name {
+        let spoken = f
+            .strip_prefix("src/")
+            .map(|rest| format!("source {}", rest));
+        out.push_str(", in ");
+        out.push_str(spoken.as_deref().unwrap_or(&f));
+    }
+    if let Some(c) = caption {
+        out.push_str(": ");
+        out.push_str(c.trim_end_matches('.'));
+    }
+    out.push('.');
+    Some(out)
 }
 
 fn collapse_multiple_newlines(input: &str) -> String {
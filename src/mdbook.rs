@@ -0,0 +1,268 @@
+use anyhow::{Context, Result, anyhow};
+use regex::Regex;
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::align::{SentenceTiming, find_sentence};
+
+/// Directory, inside the book's `src/`, holding the generated audio. mdBook copies it into
+/// the rendered book along with the other non-Markdown files.
+pub const AUDIO_DIR: &str = "audio";
+
+/// Directory, in the book root, where chapters are generated. Its intermediate files (TTS
+/// parts, chunk maps, diagnostics) stay out of `src/`, which mdBook publishes.
+pub const CACHE_DIR: &str = ".audio-book-cache";
+
+/// Extensions the audio for a chapter may have, in order of preference.
+const AUDIO_EXTENSIONS: &[&str] = &[".wav", ".mp3", ".ogg"];
+
+/// Click handler for the "play from here" links, added once per chapter.
+const SEEK_SCRIPT: &str = "<script>document.addEventListener('click',function(e){var a=e.target.closest('.audio-book-seek');if(!a)return;e.preventDefault();var p=document.getElementById('audio-book-player');p.currentTime=parseFloat(a.dataset.time);p.play();});</script>";
+
+/// `[preprocessor.audio]` settings from `book.toml`.
+#[derive(Clone, Debug, Default)]
+pub struct MdbookConfig {
+    /// Generate audio for chapters that don't have any yet (needs `GEMINI_API_KEY`).
+    pub generate: bool,
+    /// Extra command-line options passed when generating, e.g. `["--voice", "Leda"]`.
+    pub args: Vec<String>,
+}
+
+impl MdbookConfig {
+    fn from_context(context: &Value) -> Self {
+        let table = &context["config"]["preprocessor"]["audio"];
+        Self {
+            generate: table["generate"].as_bool().unwrap_or(false),
+            args: table["args"]
+                .as_array()
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Whether the preprocessor works with an mdBook renderer (`mdbook` calls `supports <renderer>`).
+pub fn supports_renderer(renderer: &str) -> bool {
+    renderer == "html"
+}
+
+/// Run as an mdBook preprocessor: read `[context, book]` JSON from stdin, optionally generate
+/// missing chapter audio, inject an audio player and "play from here" links into each chapter
+/// that has audio, and write the book JSON to stdout.
+///
+/// Enable it in `book.toml` with:
+///
+/// ```toml
+/// [preprocessor.audio]
+/// command = "rust-the-audio-book mdbook"
+/// generate = true
+/// args = ["--voice", "Leda"]
+/// ```
+pub fn run_preprocessor() -> Result<()> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .context("failed to read mdBook input")?;
+    let parsed: Value = serde_json::from_str(&input).context("invalid mdBook input")?;
+    let (context, mut book) = match parsed {
+        Value::Array(mut items) if items.len() == 2 => {
+            let book = items.pop().unwrap_or_default();
+            let context = items.pop().unwrap_or_default();
+            (context, book)
+        }
+        _ => return Err(anyhow!("expected [context, book] from mdBook")),
+    };
+
+    let config = MdbookConfig::from_context(&context);
+    let root = PathBuf::from(context["root"].as_str().unwrap_or("."));
+    let src_dir = root.join(context["config"]["book"]["src"].as_str().unwrap_or("src"));
+
+    // mdBook 0.4 calls the chapter list "sections", 0.5 "items"
+    let items = if book.get("items").is_some() {
+        &mut book["items"]
+    } else {
+        &mut book["sections"]
+    };
+    let cache_dir = root.join(CACHE_DIR);
+    process_items(items, &src_dir, &cache_dir, &config)?;
+
+    serde_json::to_writer(io::stdout(), &book).context("failed to write mdBook output")?;
+    Ok(())
+}
+
+fn process_items(
+    items: &mut Value,
+    src_dir: &Path,
+    cache_dir: &Path,
+    config: &MdbookConfig,
+) -> Result<()> {
+    let Some(items) = items.as_array_mut() else {
+        return Ok(());
+    };
+    for item in items {
+        let Some(chapter) = item.get_mut("Chapter") else {
+            continue;
+        };
+        process_chapter(chapter, src_dir, cache_dir, config)?;
+        process_items(&mut chapter["sub_items"], src_dir, cache_dir, config)?;
+    }
+    Ok(())
+}
+
+fn process_chapter(
+    chapter: &mut Value,
+    src_dir: &Path,
+    cache_dir: &Path,
+    config: &MdbookConfig,
+) -> Result<()> {
+    let Some(source) = chapter["source_path"]
+        .as_str()
+        .or_else(|| chapter["path"].as_str())
+        .map(PathBuf::from)
+    else {
+        return Ok(());
+    };
+    let Some(stem) = source.file_stem().and_then(|s| s.to_str()) else {
+        return Ok(());
+    };
+    let audio_dir = src_dir.join(AUDIO_DIR);
+
+    if config.generate && find_audio(&audio_dir, stem).is_none() {
+        generate_audio(
+            &src_dir.join(&source),
+            stem,
+            cache_dir,
+            &audio_dir,
+            &config.args,
+        )?;
+    }
+    let Some(audio_file) = find_audio(&audio_dir, stem) else {
+        return Ok(());
+    };
+
+    let timings: Vec<SentenceTiming> =
+        fs::read_to_string(audio_dir.join(format!("{}.timings.json", stem)))
+            .ok()
+            .and_then(|t| serde_json::from_str(&t).ok())
+            .unwrap_or_default();
+
+    // Links are relative to the chapter's rendered page
+    let chapter_path = chapter["path"]
+        .as_str()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| source.clone());
+    let depth = chapter_path.components().count().saturating_sub(1);
+    let href = format!("{}{}/{}", "../".repeat(depth), AUDIO_DIR, audio_file);
+
+    let content = chapter["content"].as_str().unwrap_or("");
+    let injected = inject_player(content, &href, &timings);
+    chapter["content"] = Value::String(injected);
+    Ok(())
+}
+
+/// File name of the chapter's audio in `audio_dir`, if it has been generated.
+fn find_audio(audio_dir: &Path, stem: &str) -> Option<String> {
+    AUDIO_EXTENSIONS
+        .iter()
+        .map(|ext| format!("{}{}", stem, ext))
+        .find(|name| audio_dir.join(name).exists())
+}
+
+/// Generate one chapter by running this program on it from `cache_dir`, then copy only the
+/// audio and its timing map into `audio_dir`. Its progress output goes to stderr, since
+/// stdout carries the book JSON back to mdBook.
+fn generate_audio(
+    chapter: &Path,
+    stem: &str,
+    cache_dir: &Path,
+    audio_dir: &Path,
+    args: &[String],
+) -> Result<()> {
+    let exe = std::env::current_exe().context("failed to locate the current executable")?;
+    let chapter = fs::canonicalize(chapter)
+        .with_context(|| format!("chapter not found: {}", chapter.display()))?;
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("failed to create {}", cache_dir.display()))?;
+    eprintln!("Generating audio for {}", chapter.display());
+    let status = Command::new(exe)
+        .args(args)
+        .arg(&chapter)
+        .current_dir(cache_dir)
+        .stdout(Stdio::from(io::stderr()))
+        .status()
+        .context("failed to start audio generation")?;
+    if !status.success() {
+        eprintln!(
+            "warn: audio generation failed for {} ({})",
+            chapter.display(),
+            status
+        );
+        return Ok(());
+    }
+
+    let generated_dir = cache_dir.join(AUDIO_DIR);
+    let Some(audio_file) = find_audio(&generated_dir, stem) else {
+        return Ok(());
+    };
+    fs::create_dir_all(audio_dir)
+        .with_context(|| format!("failed to create {}", audio_dir.display()))?;
+    for name in [audio_file, format!("{}.timings.json", stem)] {
+        let from = generated_dir.join(&name);
+        if from.exists() {
+            fs::copy(&from, audio_dir.join(&name))
+                .with_context(|| format!("failed to copy {}", from.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Add an `<audio>` player at the top of the chapter and a "play from here" link before each
+/// prose paragraph whose opening words match a timed sentence. Matching walks forward through
+/// the timings, so paragraphs and sentences are paired in reading order.
+pub fn inject_player(content: &str, audio_href: &str, timings: &[SentenceTiming]) -> String {
+    let re_prose = Regex::new(r"^[\p{L}\p{N}*_\[`]").unwrap();
+
+    let mut out: Vec<String> = vec![
+        format!(
+            "<div class=\"audio-book\"><audio id=\"audio-book-player\" controls preload=\"none\" src=\"{}\"></audio></div>",
+            audio_href
+        ),
+        String::new(),
+    ];
+    let mut cursor = 0usize;
+    let mut in_fence = false;
+    let mut prev_blank = true;
+    for line in content.lines() {
+        let t = line.trim_start();
+        if t.starts_with("```") {
+            in_fence = !in_fence;
+        }
+        let starts_paragraph = !in_fence && prev_blank && re_prose.is_match(t);
+        prev_blank = line.trim().is_empty();
+        if !starts_paragraph {
+            out.push(line.to_string());
+            continue;
+        }
+
+        match find_sentence(timings, cursor, line, false) {
+            Some(i) => {
+                cursor = i + 1;
+                out.push(format!(
+                    "<a class=\"audio-book-seek\" href=\"#\" data-time=\"{:.2}\" title=\"Play from here\">▶</a> {}",
+                    timings[i].start, line
+                ));
+            }
+            None => out.push(line.to_string()),
+        }
+    }
+    out.push(String::new());
+    out.push(SEEK_SCRIPT.to_string());
+    out.join("\n")
+}
@@ -0,0 +1,659 @@
+use regex::{Captures, Regex};
+
+/// Spoken-language conventions used when expanding numbers and symbols.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    /// American English: "one hundred five", "zero".
+    #[default]
+    EnUs,
+    /// British English: "one hundred and five", "nought point five".
+    EnGb,
+}
+
+impl Locale {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "en" | "en-us" => Some(Locale::EnUs),
+            "en-gb" | "en-uk" => Some(Locale::EnGb),
+            _ => None,
+        }
+    }
+
+    fn uses_and(self) -> bool {
+        self == Locale::EnGb
+    }
+
+    /// Word for a zero before the decimal point ("zero point five" / "nought point five").
+    fn leading_zero(self) -> &'static str {
+        match self {
+            Locale::EnUs => "zero",
+            Locale::EnGb => "nought",
+        }
+    }
+}
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const SCALES: [&str; 7] = [
+    "",
+    "thousand",
+    "million",
+    "billion",
+    "trillion",
+    "quadrillion",
+    "quintillion",
+];
+
+/// Units written after a number, with their singular spoken form.
+const UNITS: &[(&str, &str)] = &[
+    ("KiB", "kibibyte"),
+    ("MiB", "mebibyte"),
+    ("GiB", "gibibyte"),
+    ("TiB", "tebibyte"),
+    ("KB", "kilobyte"),
+    ("kB", "kilobyte"),
+    ("MB", "megabyte"),
+    ("GB", "gigabyte"),
+    ("TB", "terabyte"),
+    ("ns", "nanosecond"),
+    ("µs", "microsecond"),
+    ("ms", "millisecond"),
+    ("Hz", "hertz"),
+    ("kHz", "kilohertz"),
+    ("MHz", "megahertz"),
+    ("GHz", "gigahertz"),
+];
+
+/// Spoken form of operator and punctuation symbols, as read between operands.
+const SYMBOL_WORDS: &[(&str, &str)] = &[
+    ("==", "equals"),
+    ("!=", "is not equal to"),
+    ("<=", "is less than or equal to"),
+    (">=", "is greater than or equal to"),
+    ("&&", "and"),
+    ("||", "or"),
+    ("+", "plus"),
+    ("-", "minus"),
+    ("*", "times"),
+    ("/", "divided by"),
+    ("%", "modulo"),
+    ("<", "is less than"),
+    (">", "is greater than"),
+    ("+=", "plus equals"),
+    ("-=", "minus equals"),
+    ("*=", "times equals"),
+    ("/=", "divided by equals"),
+    ("%=", "modulo equals"),
+    ("&", "ampersand"),
+    ("|", "pipe"),
+    ("^", "caret"),
+    ("!", "not"),
+    ("<<", "shift left"),
+    (">>", "shift right"),
+    ("&=", "and equals"),
+    ("|=", "or equals"),
+    ("^=", "caret equals"),
+    ("<<=", "shift left equals"),
+    (">>=", "shift right equals"),
+    ("=", "assign"),
+    ("->", "returns"),
+    ("=>", "maps to"),
+    ("::", "path separator"),
+    (".", "dot"),
+    ("..", "range"),
+    ("..=", "inclusive range"),
+    ("...", "ellipsis"),
+    ("?", "question mark"),
+    ("@", "at"),
+    (",", "comma"),
+    (";", "semicolon"),
+    (":", "colon"),
+    ("_", "underscore"),
+    ("#", "hash"),
+    ("$", "dollar"),
+    ("'", "apostrophe"),
+];
+
+/// Words for an operator or punctuation symbol, e.g. `+` -> "plus", `..=` -> "inclusive
+/// range". None for anything that isn't a known symbol.
+pub fn symbol_words(symbol: &str) -> Option<&'static str> {
+    SYMBOL_WORDS
+        .iter()
+        .find(|(s, _)| *s == symbol)
+        .map(|(_, w)| *w)
+}
+
+/// Replace whitespace-separated symbols in `text` with their words, keeping any backticks
+/// around them: "`expr + expr`" -> "`expr plus expr`", "`+`" -> "`plus`". For text where
+/// symbols stand on their own, like the operator column of a table.
+pub fn spell_symbols(text: &str) -> String {
+    text.split(' ')
+        .map(|token| {
+            let bare = token.trim_matches('`');
+            match symbol_words(bare) {
+                Some(words) if !bare.is_empty() => token.replacen(bare, words, 1),
+                _ => token.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Expand numbers, ranges, versions, operators and units in plain narration text into words.
+/// Compiles the rules on each call; use a [`Normalizer`] to apply them many times.
+pub fn normalize_text(input: &str, locale: Locale) -> String {
+    Normalizer::new(locale).apply(input)
+}
+
+/// Compiled number, operator and unit reading rules for one locale.
+///
+/// Runs on the sanitized, lexicon-processed text just before it is sent. Numbers embedded in
+/// identifiers (`ch03`, `utf8`) are left alone; integer types like `i32` are read as
+/// "i thirty-two".
+pub struct Normalizer {
+    locale: Locale,
+    re_arrow: Regex,
+    re_fat_arrow: Regex,
+    /// IPv4 addresses and `localhost`, with an optional `:port`.
+    re_address: Regex,
+    /// A four-digit year after "edition" or "in", and what follows it (to skip units).
+    re_year: Regex,
+    re_semver: Regex,
+    re_short_version: Regex,
+    re_range_incl: Regex,
+    re_range: Regex,
+    re_pow: Regex,
+    /// `Chapter 3 - 5`: a dash between numbers after a noun that is counted in ranges.
+    re_prose_range: Regex,
+    /// Spaced comparison and logical operators, with their words.
+    comparisons: Vec<(Regex, &'static str)>,
+    /// Arithmetic operators, with their words.
+    arithmetic: Vec<(Regex, &'static str)>,
+    re_negative: Regex,
+    re_int_type: Regex,
+    re_percent: Regex,
+    re_unit: Regex,
+    re_ordinal: Regex,
+    re_number: Regex,
+}
+
+impl Normalizer {
+    pub fn new(locale: Locale) -> Self {
+        let comparisons = ["==", "!=", "<=", ">=", "&&", "||"]
+            .into_iter()
+            .map(|op| {
+                let re = Regex::new(&format!(r"(\w)\s+{}\s+(\w)", regex::escape(op))).unwrap();
+                (re, symbol_words(op).unwrap_or(op))
+            })
+            .collect();
+        // Arithmetic only between numbers or single-letter variables, so prose dashes
+        // ("Rust - the book") and "50 % of" are left alone
+        let arithmetic = ["+", "-", "*", "/", "%", "<", ">"]
+            .into_iter()
+            .map(|op| {
+                let re = Regex::new(&format!(
+                    r"\b(\d+|[A-Za-z])\s+{}\s+(\d+|[A-Za-z])\b",
+                    regex::escape(op)
+                ))
+                .unwrap();
+                (re, symbol_words(op).unwrap_or(op))
+            })
+            .collect();
+        let unit_alt = UNITS
+            .iter()
+            .map(|(u, _)| regex::escape(u))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        Self {
+            locale,
+            re_arrow: Regex::new(r"\s*->\s*").unwrap(),
+            re_fat_arrow: Regex::new(r"\s*=>\s*").unwrap(),
+            re_address: Regex::new(
+                r"\b(localhost|\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})(?::(\d{1,5}))?\b",
+            )
+            .unwrap(),
+            re_year: Regex::new(&format!(
+                r"(?i)\b(edition|in)\s+(\d{{4}})\b(\s*%|\s*(?:{})\b)?",
+                unit_alt
+            ))
+            .unwrap(),
+            re_semver: Regex::new(r"\b(\d+)\.(\d+|x)\.(\d+|x)\b").unwrap(),
+            re_short_version: Regex::new(
+                r"(?i)\b(version|rust|rustc|cargo|edition|v)\s*(\d+)\.(\d+|x)\b",
+            )
+            .unwrap(),
+            re_range_incl: Regex::new(r"\b(\d+)\s*\.\.=\s*(\d+)\b").unwrap(),
+            re_range: Regex::new(r"\b(\d+)\s*\.\.\s*(\d+)\b").unwrap(),
+            re_pow: Regex::new(r"\b(\d+)\s*\^\s*(\d+)\b").unwrap(),
+            re_prose_range: Regex::new(
+                r"(?i)\b(chapters?|sections?|pages?|listings?|lines?|figures?|tables?|steps?|parts?|items?)\s+(\d+)\s*[-–]\s*(\d+)\b",
+            )
+            .unwrap(),
+            comparisons,
+            arithmetic,
+            re_negative: Regex::new(r"(^|[\s(])-(\d)").unwrap(),
+            re_int_type: Regex::new(r"\b([iuf])(8|16|32|64|128)\b").unwrap(),
+            re_percent: Regex::new(r"\b(\d+(?:\.\d+)?)\s*%").unwrap(),
+            re_unit: Regex::new(&format!(r"\b(\d+(?:\.\d+)?)\s*({})\b", unit_alt)).unwrap(),
+            re_ordinal: Regex::new(r"\b(\d+)(st|nd|rd|th)\b").unwrap(),
+            re_number: Regex::new(r"\b\d{1,3}(?:,\d{3})+(?:\.\d+)?\b|\b\d+(?:\.\d+)?\b").unwrap(),
+        }
+    }
+
+    /// Expand numbers, ranges, versions, operators and units in `input` into words.
+    pub fn apply(&self, input: &str) -> String {
+        let locale = self.locale;
+        let mut text = input.to_string();
+
+        // Path separators and arrows: `u32::MAX` -> "u32 MAX", `-> i32` -> "returns i32"
+        text = text.replace("::", " ");
+        text = self.re_arrow.replace_all(&text, " returns ").into_owned();
+        text = self
+            .re_fat_arrow
+            .replace_all(&text, " maps to ")
+            .into_owned();
+
+        // Addresses before versions, so `127.0.0.1:7878` isn't read as a version: digit
+        // groups are read digit by digit, "one two seven dot zero dot zero dot one port ..."
+        text = self
+            .re_address
+            .replace_all(&text, |c: &Captures| {
+                let host = if &c[1] == "localhost" {
+                    c[1].to_string()
+                } else {
+                    c[1].split('.')
+                        .map(digits)
+                        .collect::<Vec<_>>()
+                        .join(" dot ")
+                };
+                match c.get(2) {
+                    Some(port) => format!("{} port {}", host, digits(port.as_str())),
+                    None => host,
+                }
+            })
+            .into_owned();
+
+        // Years after "edition" or "in": "Edition 2021" -> "Edition twenty twenty-one",
+        // but "in 2048 KiB" stays a number
+        text = self
+            .re_year
+            .replace_all(&text, |c: &Captures| {
+                match (c.get(3), c[2].parse::<u64>()) {
+                    (None, Ok(year)) if (1100..=2099).contains(&year) => {
+                        format!("{} {}", &c[1], year_words(year, locale))
+                    }
+                    _ => c[0].to_string(),
+                }
+            })
+            .into_owned();
+
+        // Semantic versions (always three parts), or two parts after a version-ish word
+        text = self
+            .re_semver
+            .replace_all(&text, |c: &Captures| {
+                [&c[1], &c[2], &c[3]]
+                    .iter()
+                    .map(|p| version_part(p, locale))
+                    .collect::<Vec<_>>()
+                    .join(" point ")
+            })
+            .into_owned();
+        text = self
+            .re_short_version
+            .replace_all(&text, |c: &Captures| {
+                let word = if c[1].eq_ignore_ascii_case("v") {
+                    "version"
+                } else {
+                    &c[1]
+                };
+                format!(
+                    "{} {} point {}",
+                    word,
+                    version_part(&c[2], locale),
+                    version_part(&c[3], locale)
+                )
+            })
+            .into_owned();
+
+        // Ranges: 0..=10 and 0..10
+        text = self
+            .re_range_incl
+            .replace_all(&text, "$1 through $2 inclusive")
+            .into_owned();
+        text = self
+            .re_range
+            .replace_all(&text, "$1 up to but not including $2")
+            .into_owned();
+
+        // Powers: 2^31 -> 2 to the power of 31
+        text = self
+            .re_pow
+            .replace_all(&text, "$1 to the power of $2")
+            .into_owned();
+
+        // Ranges in prose: "Chapter 3 - 5" -> "Chapter 3 to 5", not "3 minus 5"
+        text = self
+            .re_prose_range
+            .replace_all(&text, "$1 $2 to $3")
+            .into_owned();
+
+        // Spaced comparison and logical operators between any operands
+        for (re, word) in &self.comparisons {
+            text = re
+                .replace_all(&text, format!("$1 {} $2", word).as_str())
+                .into_owned();
+        }
+
+        for (re, word) in &self.arithmetic {
+            // Run twice so chains like `a + b + c` (sharing an operand) are all caught
+            for _ in 0..2 {
+                text = re
+                    .replace_all(&text, format!("$1 {} $2", word).as_str())
+                    .into_owned();
+            }
+        }
+
+        // Negative numbers at the start of a token
+        text = self
+            .re_negative
+            .replace_all(&text, "${1}minus $2")
+            .into_owned();
+
+        // Integer and float types: i32 -> i thirty-two
+        text = self
+            .re_int_type
+            .replace_all(&text, |c: &Captures| {
+                format!("{} {}", &c[1], cardinal(c[2].parse().unwrap_or(0), locale))
+            })
+            .into_owned();
+
+        // Percentages
+        text = self
+            .re_percent
+            .replace_all(&text, |c: &Captures| {
+                format!("{} percent", number_words(&c[1], locale))
+            })
+            .into_owned();
+
+        // Numbers with units: 4 KiB -> four kibibytes
+        text = self
+            .re_unit
+            .replace_all(&text, |c: &Captures| {
+                let unit = UNITS
+                    .iter()
+                    .find(|(u, _)| *u == &c[2])
+                    .map(|(_, w)| *w)
+                    .unwrap_or("");
+                let plural = &c[1] != "1" && !unit.ends_with("hertz");
+                format!(
+                    "{} {}{}",
+                    number_words(&c[1], locale),
+                    unit,
+                    if plural { "s" } else { "" }
+                )
+            })
+            .into_owned();
+
+        // Ordinals: 1st, 2nd, 3rd, 4th
+        text = self
+            .re_ordinal
+            .replace_all(&text, |c: &Captures| match c[1].parse::<u64>() {
+                Ok(n) => ordinal(n, locale),
+                Err(_) => c[0].to_string(),
+            })
+            .into_owned();
+
+        // Remaining numbers: 1,000,000 / 3.14 / 42
+        text = self
+            .re_number
+            .replace_all(&text, |c: &Captures| number_words(&c[0], locale))
+            .into_owned();
+
+        text
+    }
+}
+
+fn version_part(p: &str, locale: Locale) -> String {
+    if p == "x" {
+        return "x".to_string();
+    }
+    match p.parse::<u64>() {
+        Ok(n) => cardinal(n, locale),
+        Err(_) => p.to_string(),
+    }
+}
+
+/// A year read in pairs of digits: 1999 -> "nineteen ninety-nine", 2021 -> "twenty
+/// twenty-one", 1905 -> "nineteen oh five", 1900 -> "nineteen hundred". 2000 to 2009 are
+/// read as cardinals ("two thousand five").
+fn year_words(year: u64, locale: Locale) -> String {
+    if (2000..2010).contains(&year) {
+        return cardinal(year, locale);
+    }
+    let (century, rest) = ((year / 100) as u32, (year % 100) as u32);
+    let rest = match rest {
+        0 => "hundred".to_string(),
+        1..=9 => format!("oh {}", ONES[rest as usize]),
+        _ => below_hundred(rest),
+    };
+    format!("{} {}", below_hundred(century), rest)
+}
+
+/// Words for a written number: integers (with optional `,` grouping) and decimals.
+/// Leading zeros and very long digit runs are read digit by digit.
+pub fn number_words(written: &str, locale: Locale) -> String {
+    let plain = written.replace(',', "");
+    let (int_part, frac_part) = match plain.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (plain.as_str(), None),
+    };
+
+    let int_words = if int_part.len() > 1 && int_part.starts_with('0') {
+        digits(int_part)
+    } else {
+        match int_part.parse::<u64>() {
+            Ok(0) if frac_part.is_some() => locale.leading_zero().to_string(),
+            Ok(n) => cardinal(n, locale),
+            Err(_) => digits(int_part),
+        }
+    };
+
+    match frac_part {
+        Some(f) => format!("{} point {}", int_words, digits(f)),
+        None => int_words,
+    }
+}
+
+fn digits(s: &str) -> String {
+    s.chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ONES[d as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Cardinal number words, e.g. 2147483647 -> "two billion one hundred forty-seven million ...".
+pub fn cardinal(n: u64, locale: Locale) -> String {
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push((rest % 1000) as u32);
+        rest /= 1000;
+    }
+
+    let mut words: Vec<String> = Vec::new();
+    for (scale, &g) in groups.iter().enumerate().rev() {
+        if g == 0 {
+            continue;
+        }
+        // British English joins a trailing group below one hundred with "and"
+        if scale == 0 && g < 100 && n >= 1000 && locale.uses_and() {
+            words.push("and".to_string());
+        }
+        words.push(below_thousand(g, locale));
+        if scale > 0 {
+            words.push(SCALES[scale].to_string());
+        }
+    }
+    words.join(" ")
+}
+
+fn below_thousand(n: u32, locale: Locale) -> String {
+    let hundreds = n / 100;
+    let rest = n % 100;
+    let mut parts = Vec::new();
+    if hundreds > 0 {
+        parts.push(format!("{} hundred", ONES[hundreds as usize]));
+    }
+    if rest > 0 {
+        if hundreds > 0 && locale.uses_and() {
+            parts.push("and".to_string());
+        }
+        parts.push(below_hundred(rest));
+    }
+    parts.join(" ")
+}
+
+fn below_hundred(n: u32) -> String {
+    if n < 20 {
+        ONES[n as usize].to_string()
+    } else if n.is_multiple_of(10) {
+        TENS[(n / 10) as usize].to_string()
+    } else {
+        format!("{}-{}", TENS[(n / 10) as usize], ONES[(n % 10) as usize])
+    }
+}
+
+/// Ordinal number words, e.g. 21 -> "twenty-first".
+pub fn ordinal(n: u64, locale: Locale) -> String {
+    let words = cardinal(n, locale);
+    let (head, last) = match words.rfind([' ', '-']) {
+        Some(i) => words.split_at(i + 1),
+        None => ("", words.as_str()),
+    };
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        w if w.ends_with('y') => format!("{}ieth", &w[..w.len() - 1]),
+        w => format!("{}th", w),
+    };
+    format!("{}{}", head, last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_written_forms() {
+        let cases = [
+            ("It is 50% done.", "It is fifty percent done."),
+            (
+                "Wait 3 ms for 2 KiB.",
+                "Wait three milliseconds for two kibibytes.",
+            ),
+            (
+                "The 1st, 2nd and 23rd items.",
+                "The first, second and twenty-third items.",
+            ),
+            ("1,000,000 and 3.14", "one million and three point one four"),
+            ("-5 degrees", "minus five degrees"),
+            ("See ch03 and utf8.", "See ch03 and utf8."),
+            ("fn f() -> i32", "fn f() returns i thirty-two"),
+            (
+                "Rust 1.85.0 and version 1.2",
+                "Rust one point eighty-five point zero and version one point two",
+            ),
+            (
+                "Connect to 127.0.0.1:7878 now.",
+                "Connect to one two seven dot zero dot zero dot one port seven eight seven eight now.",
+            ),
+            (
+                "Open localhost:8080.",
+                "Open localhost port eight zero eight zero.",
+            ),
+            (
+                "Edition 2021 and edition 2018.",
+                "Edition twenty twenty-one and edition twenty eighteen.",
+            ),
+            (
+                "in 2015, in 1999, in 2005, in 1905",
+                "in twenty fifteen, in nineteen ninety-nine, in two thousand five, in nineteen oh five",
+            ),
+            (
+                "Read in 2048 KiB chunks.",
+                "Read in two thousand forty-eight kibibytes chunks.",
+            ),
+            (
+                "Chapter 3 - 5 and pages 10–12.",
+                "Chapter three to five and pages ten to twelve.",
+            ),
+            (
+                "2^31 - 1 and a - b and 5 - 3.",
+                "two to the power of thirty-one minus one and a minus b and five minus three.",
+            ),
+            (
+                "a + b and x == y and Rust - the book and 2 < 3",
+                "a plus b and x equals y and Rust - the book and two is less than three",
+            ),
+        ];
+        let normalizer = Normalizer::new(Locale::EnUs);
+        for (input, expected) in cases {
+            assert_eq!(normalizer.apply(input), expected, "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn british_locale_uses_and_and_nought() {
+        assert_eq!(
+            normalize_text("105 and 0.5", Locale::EnGb),
+            "one hundred and five and nought point five"
+        );
+        assert_eq!(
+            normalize_text("105 and 0.5", Locale::EnUs),
+            "one hundred five and zero point five"
+        );
+    }
+
+    #[test]
+    fn spells_operator_tokens_outside_code_spans() {
+        assert_eq!(symbol_words("!="), Some("is not equal to"));
+        assert_eq!(symbol_words("x"), None);
+        assert_eq!(
+            spell_symbols("a >= b and `x+y` and c != d"),
+            "a is greater than or equal to b and `x+y` and c is not equal to d"
+        );
+    }
+}
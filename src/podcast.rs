@@ -0,0 +1,248 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::align::SentenceTiming;
+use crate::audio::{audio_mime_for_extension, wav_duration_from_path};
+use crate::ssml::escape_xml;
+use crate::summary::{BookMeta, Summary};
+
+/// File names of the feed and index page written into the audio directory.
+pub const FEED_FILE: &str = "feed.xml";
+pub const INDEX_FILE: &str = "index.html";
+
+/// Channel settings for the podcast; anything left unset comes from `book.toml`.
+#[derive(Clone, Debug, Default)]
+pub struct PodcastOptions {
+    /// Public URL the audio directory will be served from; enclosure and feed links are
+    /// built from it.
+    pub base_url: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// URL of the cover art (Apple asks for a 1400-3000 px square JPEG or PNG).
+    pub image: Option<String>,
+}
+
+/// One generated chapter published as an episode.
+#[derive(Clone, Debug)]
+pub struct Episode {
+    /// 1-based position in reading order.
+    pub number: usize,
+    pub title: String,
+    /// Audio file name inside the audio directory.
+    pub file: String,
+    pub mime: &'static str,
+    /// File size in bytes, for the enclosure.
+    pub length: u64,
+    pub duration: Option<f64>,
+    pub published: DateTime<Utc>,
+    /// WebVTT captions next to the audio, if they were written.
+    pub captions: Option<String>,
+}
+
+/// Scan `audio_dir` for chapter audio and write a podcast RSS 2.0 feed (`feed.xml`) and a
+/// static index page (`index.html`) next to it. Returns the number of episodes.
+pub fn write_podcast(src_dir: &Path, audio_dir: &Path, opts: &PodcastOptions) -> Result<usize> {
+    let book = BookMeta::from_book(src_dir);
+    let episodes = collect_episodes(src_dir, audio_dir)?;
+    if episodes.is_empty() {
+        return Err(anyhow!("no chapter audio found in {}", audio_dir.display()));
+    }
+    for (name, doc) in [
+        (FEED_FILE, to_rss(&book, opts, &episodes)),
+        (INDEX_FILE, to_index_html(&book, opts, &episodes)),
+    ] {
+        let path = audio_dir.join(name);
+        fs::write(&path, doc).with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(episodes.len())
+}
+
+/// Audio files in `audio_dir` as episodes: chapters listed in `SUMMARY.md` first, in reading
+/// order and with their summary titles, then any other audio by file name. When a chapter
+/// has audio in several formats, the first by file name is used.
+pub fn collect_episodes(src_dir: &Path, audio_dir: &Path) -> Result<Vec<Episode>> {
+    let mut files: Vec<(PathBuf, &'static str)> = fs::read_dir(audio_dir)
+        .with_context(|| format!("failed to read {}", audio_dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .filter_map(|p| {
+            let mime = audio_mime_for_extension(p.extension()?.to_str()?)?;
+            Some((p, mime))
+        })
+        .collect();
+    files.sort();
+    files.dedup_by(|a, b| a.0.file_stem() == b.0.file_stem());
+
+    let summary = Summary::load(&src_dir.join("SUMMARY.md")).unwrap_or_default();
+    let position = |stem: &str| {
+        summary.entries.iter().position(|e| {
+            e.path
+                .as_ref()
+                .is_some_and(|p| p.file_stem().and_then(|s| s.to_str()) == Some(stem))
+        })
+    };
+    let stem_of = |p: &Path| {
+        p.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    files.sort_by_key(|(p, _)| (position(&stem_of(p)).unwrap_or(usize::MAX), stem_of(p)));
+
+    let mut episodes = Vec::new();
+    for (i, (path, mime)) in files.into_iter().enumerate() {
+        let stem = stem_of(&path);
+        let title = position(&stem)
+            .map(|k| summary.entries[k].title.clone())
+            .unwrap_or_else(|| stem.clone());
+        let meta =
+            fs::metadata(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let captions = format!("{}.vtt", stem);
+        episodes.push(Episode {
+            number: i + 1,
+            title,
+            file: path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            mime,
+            length: meta.len(),
+            duration: audio_duration(&path, audio_dir, &stem),
+            published: meta
+                .modified()
+                .map(DateTime::from)
+                .unwrap_or_else(|_| Utc::now()),
+            captions: audio_dir.join(&captions).exists().then_some(captions),
+        });
+    }
+    Ok(episodes)
+}
+
+/// Playing time from the WAV header, or else from the end of the chapter's timing map.
+fn audio_duration(path: &Path, audio_dir: &Path, stem: &str) -> Option<f64> {
+    if path.extension().is_some_and(|e| e == "wav")
+        && let Ok(d) = wav_duration_from_path(path)
+    {
+        return Some(d);
+    }
+    let timings: Vec<SentenceTiming> = serde_json::from_str(
+        &fs::read_to_string(audio_dir.join(format!("{}.timings.json", stem))).ok()?,
+    )
+    .ok()?;
+    timings.last().map(|t| t.end)
+}
+
+/// RSS 2.0 feed with the iTunes podcast tags. Episodes are published as a serial show, so
+/// podcast apps play them in book order.
+pub fn to_rss(book: &BookMeta, opts: &PodcastOptions, episodes: &[Episode]) -> String {
+    let base = opts.base_url.trim_end_matches('/');
+    let title = opts.title.as_deref().unwrap_or(&book.title);
+    let author = opts
+        .author
+        .clone()
+        .unwrap_or_else(|| book.authors.join(", "));
+    let description = channel_description(book, opts);
+
+    let mut items = String::new();
+    for ep in episodes {
+        let duration = ep
+            .duration
+            .map(|d| format!("<itunes:duration>{}</itunes:duration>\n", clock(d)))
+            .unwrap_or_default();
+        items.push_str(&format!(
+            "<item>\n<title>{title}</title>\n<itunes:title>{title}</itunes:title>\n<itunes:episode>{number}</itunes:episode>\n<itunes:episodeType>full</itunes:episodeType>\n<enclosure url=\"{base}/{file}\" length=\"{length}\" type=\"{mime}\"/>\n<guid isPermaLink=\"false\">{file}</guid>\n<pubDate>{date}</pubDate>\n{duration}<itunes:explicit>false</itunes:explicit>\n</item>\n",
+            title = escape_xml(&ep.title),
+            number = ep.number,
+            base = escape_xml(base),
+            file = escape_xml(&ep.file),
+            length = ep.length,
+            mime = ep.mime,
+            date = ep.published.to_rfc2822(),
+            duration = duration
+        ));
+    }
+    let image = opts
+        .image
+        .as_ref()
+        .map(|url| format!("<itunes:image href=\"{}\"/>\n", escape_xml(url)))
+        .unwrap_or_default();
+    let author_tag = if author.is_empty() {
+        String::new()
+    } else {
+        format!("<itunes:author>{}</itunes:author>\n", escape_xml(&author))
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n<title>{title}</title>\n<link>{base}/{index}</link>\n<atom:link href=\"{base}/{feed}\" rel=\"self\" type=\"application/rss+xml\"/>\n<description>{description}</description>\n<language>{language}</language>\n<lastBuildDate>{now}</lastBuildDate>\n{author}{image}<itunes:category text=\"Technology\"/>\n<itunes:explicit>false</itunes:explicit>\n<itunes:type>serial</itunes:type>\n{items}</channel>\n</rss>\n",
+        title = escape_xml(title),
+        base = escape_xml(base),
+        index = INDEX_FILE,
+        feed = FEED_FILE,
+        description = escape_xml(&description),
+        language = escape_xml(&book.language),
+        now = Utc::now().to_rfc2822(),
+        author = author_tag,
+        image = image,
+        items = items
+    )
+}
+
+/// Static page listing the episodes with a player each, ready to upload with the audio.
+pub fn to_index_html(book: &BookMeta, opts: &PodcastOptions, episodes: &[Episode]) -> String {
+    let title = opts.title.as_deref().unwrap_or(&book.title);
+    let mut list = String::new();
+    for ep in episodes {
+        let track = ep
+            .captions
+            .as_ref()
+            .map(|vtt| {
+                format!(
+                    "<track kind=\"captions\" src=\"{}\" srclang=\"{}\" label=\"Captions\">",
+                    escape_xml(vtt),
+                    escape_xml(&book.language)
+                )
+            })
+            .unwrap_or_default();
+        let duration = ep
+            .duration
+            .map(|d| format!(" <span class=\"duration\">{}</span>", clock(d)))
+            .unwrap_or_default();
+        list.push_str(&format!(
+            "<li>\n<h2>{number}. {title}{duration}</h2>\n<audio controls preload=\"none\" src=\"{file}\">{track}</audio>\n<a href=\"{file}\" download>Download</a>\n</li>\n",
+            number = ep.number,
+            title = escape_xml(&ep.title),
+            duration = duration,
+            file = escape_xml(&ep.file),
+            track = track
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"{language}\">\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{title}</title>\n<link rel=\"alternate\" type=\"application/rss+xml\" title=\"{title}\" href=\"{feed}\">\n<style>body {{ font-family: sans-serif; max-width: 48em; margin: 2em auto; padding: 0 1em; }} ol {{ list-style: none; padding: 0; }} li {{ margin-bottom: 1.5em; }} h2 {{ font-size: 1.1em; }} .duration {{ color: #666; font-weight: normal; }} audio {{ width: 100%; }}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<p>{description}</p>\n<p><a href=\"{feed}\">Podcast feed (RSS)</a></p>\n<ol>\n{list}</ol>\n</body>\n</html>\n",
+        language = escape_xml(&book.language),
+        title = escape_xml(title),
+        feed = FEED_FILE,
+        description = escape_xml(&channel_description(book, opts)),
+        list = list
+    )
+}
+
+fn channel_description(book: &BookMeta, opts: &PodcastOptions) -> String {
+    opts.description
+        .clone()
+        .or_else(|| book.description.clone())
+        .unwrap_or_else(|| format!("Narrated chapters of {}.", book.title))
+}
+
+/// `HH:MM:SS`, as iTunes expects for durations.
+fn clock(secs: f64) -> String {
+    let total = secs.max(0.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        total / 3600,
+        total / 60 % 60,
+        total % 60
+    )
+}
@@ -0,0 +1,472 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::audio::audio_mime_for_extension;
+use crate::podcast::{
+    FEED_FILE, INDEX_FILE, PodcastOptions, collect_episodes, to_index_html, to_rss,
+};
+use crate::summary::BookMeta;
+use crate::util::now_ts;
+
+/// Largest request head (request line and headers) accepted.
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+/// How long a client may take to send its request head.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the pieces a file body is streamed in.
+const COPY_BUF_BYTES: usize = 64 * 1024;
+
+/// What the server needs to answer requests.
+struct Site {
+    src_dir: PathBuf,
+    audio_dir: PathBuf,
+    podcast: PodcastOptions,
+}
+
+/// A parsed request head.
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+/// Serve `audio_dir` over HTTP on `addr` until the process is stopped.
+///
+/// Files are sent with `Accept-Ranges: bytes` and single `Range` requests are answered with
+/// `206 Partial Content`, so players can seek and phones can stream long chapters. `/` is the
+/// player page and `/feed.xml` the podcast feed, both built per request from the audio
+/// present at the time; their links use the request's `Host`, so the feed works from any
+/// device on the LAN. `podcast.base_url` is ignored.
+pub async fn serve(
+    addr: SocketAddr,
+    src_dir: &Path,
+    audio_dir: &Path,
+    podcast: PodcastOptions,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to listen on {}", addr))?;
+    let local = listener.local_addr()?;
+    println!("Serving {} on http://{}/", audio_dir.display(), local);
+    if local.ip().is_unspecified()
+        && let Some(ip) = lan_ip()
+    {
+        println!(
+            "On the LAN: http://{}:{}/ (feed: http://{}:{}/{})",
+            ip,
+            local.port(),
+            ip,
+            local.port(),
+            FEED_FILE
+        );
+    }
+
+    let site = Arc::new(Site {
+        src_dir: src_dir.to_path_buf(),
+        audio_dir: audio_dir.to_path_buf(),
+        podcast,
+    });
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let site = Arc::clone(&site);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer, &site).await {
+                eprintln!("{} | {}: {:#}", now_ts(), peer, e);
+            }
+        });
+    }
+}
+
+/// Address this machine uses to reach other hosts, for printing a URL phones can open.
+/// Connecting a UDP socket sends nothing; it only selects the outgoing interface.
+fn lan_ip() -> Option<std::net::IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Answer one request, then close the connection.
+async fn handle_connection(stream: TcpStream, peer: SocketAddr, site: &Site) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let Some(req) = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out reading request"))??
+    else {
+        return Ok(());
+    };
+    let status = respond(stream.get_mut(), &req, site).await?;
+    println!(
+        "{} | {} {} {} -> {}",
+        now_ts(),
+        peer,
+        req.method,
+        req.path,
+        status
+    );
+    Ok(())
+}
+
+/// Read the request line and headers; None when the client closed without sending one.
+/// At most `MAX_HEAD_BYTES` are read, however the head is split into lines.
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Option<Request>> {
+    let mut limited = stream.take(MAX_HEAD_BYTES as u64);
+    let mut head = Vec::new();
+    loop {
+        let n = limited.read_until(b'\n', &mut head).await?;
+        if n == 0 {
+            if head.len() >= MAX_HEAD_BYTES {
+                anyhow::bail!("request head too large");
+            }
+            return Ok(None);
+        }
+        if head.ends_with(b"\r\n\r\n") || head.ends_with(b"\n\n") {
+            break;
+        }
+    }
+    let text = String::from_utf8_lossy(&head);
+    let mut lines = text.lines();
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or("/");
+    let path = percent_decode(target.split(['?', '#']).next().unwrap_or("/"));
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+    }))
+}
+
+/// Write the response for `req` and return its status code.
+async fn respond(stream: &mut TcpStream, req: &Request, site: &Site) -> Result<u16> {
+    if req.method != "GET" && req.method != "HEAD" {
+        send(
+            stream,
+            405,
+            &[("Allow", "GET, HEAD".to_string())],
+            "text/plain",
+            b"method not allowed\n",
+            false,
+        )
+        .await?;
+        return Ok(405);
+    }
+    let head_only = req.method == "HEAD";
+
+    let path = req.path.trim_start_matches('/');
+    if path.is_empty() || path == INDEX_FILE || path == FEED_FILE {
+        // Scanning reads SUMMARY.md, book.toml and every WAV header: keep it off the runtime
+        let (src_dir, audio_dir) = (site.src_dir.clone(), site.audio_dir.clone());
+        let scanned = tokio::task::spawn_blocking(move || {
+            let book = BookMeta::from_book(&src_dir);
+            collect_episodes(&src_dir, &audio_dir).map(|episodes| (book, episodes))
+        })
+        .await?;
+        let (book, episodes) = match scanned {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                let body = format!("{:#}\n", e);
+                send(stream, 500, &[], "text/plain", body.as_bytes(), head_only).await?;
+                return Ok(500);
+            }
+        };
+        let (body, content_type) = if path == FEED_FILE {
+            let host = req.headers.get("host").cloned().unwrap_or_else(|| {
+                stream
+                    .local_addr()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default()
+            });
+            let podcast = PodcastOptions {
+                base_url: format!("http://{}", host),
+                ..site.podcast.clone()
+            };
+            (
+                to_rss(&book, &podcast, &episodes),
+                "application/rss+xml; charset=utf-8",
+            )
+        } else {
+            (
+                to_index_html(&book, &site.podcast, &episodes),
+                "text/html; charset=utf-8",
+            )
+        };
+        send(stream, 200, &[], content_type, body.as_bytes(), head_only).await?;
+        return Ok(200);
+    }
+
+    let Some(file) = resolve(&site.audio_dir, path) else {
+        send(stream, 404, &[], "text/plain", b"not found\n", head_only).await?;
+        return Ok(404);
+    };
+    send_file(stream, &file, req.headers.get("range"), head_only).await
+}
+
+/// File under `root` for a request path, refusing anything that would leave `root` and
+/// anything that isn't published (see [`is_published`]).
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let rel = Path::new(path);
+    if !rel.components().all(|c| matches!(c, Component::Normal(_))) || !is_published(rel) {
+        return None;
+    }
+    let full = root.join(rel);
+    full.is_file().then_some(full)
+}
+
+/// Whether a file under `audio/` may be served: chapter audio, captions and timing maps.
+/// Everything else there (diagnostics, TTS input, chunk maps, SSML, image descriptions,
+/// unfinished `.part` files) stays private.
+fn is_published(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+    audio_mime_for_extension(ext).is_some()
+        || matches!(ext, "vtt" | "srt")
+        || name.ends_with(".timings.json")
+}
+
+/// Send a file, or the part of it asked for by a `Range: bytes=...` header.
+async fn send_file(
+    stream: &mut TcpStream,
+    file: &Path,
+    range: Option<&String>,
+    head_only: bool,
+) -> Result<u16> {
+    let mut f = tokio::fs::File::open(file)
+        .await
+        .with_context(|| format!("failed to open {}", file.display()))?;
+    let len = f.metadata().await?.len();
+    let content_type = content_type(file);
+
+    let (status, start, end) = match range.map(|r| parse_range(r, len)) {
+        Some(Some(Ok((start, end)))) => (206, start, end),
+        Some(Some(Err(()))) => {
+            let headers = [("Content-Range", format!("bytes */{}", len))];
+            send(
+                stream,
+                416,
+                &headers,
+                "text/plain",
+                b"range not satisfiable\n",
+                head_only,
+            )
+            .await?;
+            return Ok(416);
+        }
+        // No Range header, or one we don't handle (multiple ranges): send the whole file
+        _ => (200, 0, len.saturating_sub(1)),
+    };
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status,
+        reason(status),
+        content_type,
+        body_len
+    );
+    if status == 206 {
+        head.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start, end, len
+        ));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    if !head_only && body_len > 0 {
+        f.seek(std::io::SeekFrom::Start(start)).await?;
+        let mut body = f.take(body_len);
+        let mut buf = vec![0u8; COPY_BUF_BYTES];
+        loop {
+            let n = body.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stream.write_all(&buf[..n]).await?;
+        }
+    }
+    stream.flush().await?;
+    Ok(status)
+}
+
+/// Parse a single-range `bytes=start-end`, `bytes=start-` or `bytes=-suffix` header into an
+/// inclusive byte range of a `len`-byte file. None for headers that are ignored (multiple
+/// ranges, other units, bad syntax); Err for a range outside the file.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = if last.is_empty() {
+            len.saturating_sub(1)
+        } else {
+            let end: u64 = last.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            end.min(len.saturating_sub(1))
+        };
+        if start >= len {
+            return Some(Err(()));
+        }
+        (start, end)
+    };
+    Some(Ok(range))
+}
+
+async fn send(
+    stream: &mut TcpStream,
+    status: u16,
+    headers: &[(&str, String)],
+    content_type: &str,
+    body: &[u8],
+    head_only: bool,
+) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    if !head_only {
+        stream.write_all(body).await?;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn content_type(file: &Path) -> &'static str {
+    let ext = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if let Some(mime) = audio_mime_for_extension(&ext) {
+        return mime;
+    }
+    match ext.as_str() {
+        "json" => "application/json",
+        "vtt" => "text/vtt; charset=utf-8",
+        "srt" => "application/x-subrip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decode `%XX` escapes in a request path.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(b) = s
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+        {
+            out.push(b);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Range = Option<Result<(u64, u64), ()>>;
+
+    #[test]
+    fn parse_range_cases() {
+        let cases: &[(&str, u64, Range)] = &[
+            ("bytes=0-99", 1000, Some(Ok((0, 99)))),
+            ("bytes=0-0", 1000, Some(Ok((0, 0)))),
+            // Open-ended and past the end are clamped to the last byte
+            ("bytes=500-", 1000, Some(Ok((500, 999)))),
+            ("bytes=900-5000", 1000, Some(Ok((900, 999)))),
+            // Suffix ranges count from the end; longer than the file means all of it
+            ("bytes=-100", 1000, Some(Ok((900, 999)))),
+            ("bytes=-5000", 1000, Some(Ok((0, 999)))),
+            // Unsatisfiable
+            ("bytes=1000-", 1000, Some(Err(()))),
+            ("bytes=-0", 1000, Some(Err(()))),
+            ("bytes=0-10", 0, Some(Err(()))),
+            ("bytes=-10", 0, Some(Err(()))),
+            // Ignored: multiple ranges, other units, malformed or reversed
+            ("bytes=0-1,5-9", 1000, None),
+            ("items=0-1", 1000, None),
+            ("bytes=abc", 1000, None),
+            ("bytes=x-5", 1000, None),
+            ("bytes=9-5", 1000, None),
+            (" bytes= 10 - 20 ", 1000, Some(Ok((10, 20)))),
+        ];
+        for (header, len, expected) in cases {
+            assert_eq!(
+                parse_range(header, *len),
+                *expected,
+                "{} of {}",
+                header,
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_refuses_escapes_and_private_files() {
+        assert!(!is_published(Path::new("ch01.diagnostics.txt")));
+        assert!(!is_published(Path::new("ch01.wav.part")));
+        assert!(!is_published(Path::new("ch01.chunks.json")));
+        assert!(!is_published(Path::new("temp/ch01.md")));
+        assert!(is_published(Path::new("ch01.wav")));
+        assert!(is_published(Path::new("CH01.MP3")));
+        assert!(is_published(Path::new("ch01.vtt")));
+        assert!(is_published(Path::new("ch01.srt")));
+        assert!(is_published(Path::new("ch01.timings.json")));
+        let root = std::env::temp_dir();
+        assert_eq!(resolve(&root, "../etc/passwd.wav"), None);
+        assert_eq!(resolve(&root, "/etc/passwd.wav"), None);
+    }
+}
@@ -0,0 +1,205 @@
+use regex::{Captures, Regex};
+
+use crate::headings::HeadingOptions;
+use crate::lexicon::{Lexicon, LexiconEntry};
+use crate::markdown::strip_markup_for_speech;
+
+/// Pause inserted after each paragraph.
+pub const PARAGRAPH_BREAK_MS: u32 = 400;
+
+/// A fragment of the SSML being built: either raw text still to be escaped and
+/// rewritten, or finished markup that later passes must leave alone.
+enum Piece {
+    Text(String),
+    Markup(String),
+}
+
+/// Convert chapter Markdown into an SSML body (without the `<speak>` root).
+///
+/// Headings get `<break>`s sized by `headings`, paragraphs a short one, `*`/`**` emphasis becomes `<emphasis>`, inline code
+/// and bare numbers get `<say-as>`, and lexicon terms become `<phoneme>` (when the entry has
+/// an `ipa` transcription) or `<sub alias>`. Each paragraph is kept on one line so the output
+/// can be chunked on line boundaries without splitting an element.
+pub fn markdown_to_ssml(input: &str, lexicon: &Lexicon, headings: &HeadingOptions) -> String {
+    let text = strip_markup_for_speech(input);
+
+    let re_heading = Regex::new(r"^\s*#{1,6}\s*(.*)$").unwrap();
+    let re_blockquote = Regex::new(r"^\s*>+\s*").unwrap();
+    let re_bullet = Regex::new(r"^\s*[-*+]\s+").unwrap();
+    let re_numbered = Regex::new(r"^\s*\d+[\.)]\s+").unwrap();
+
+    let mut out = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(caps) = re_heading.captures(line) {
+            let title = inline_to_ssml(caps[1].trim(), lexicon);
+            if !title.is_empty() {
+                out.push(format!(
+                    "<break time=\"{}ms\"/>{}<break time=\"{}ms\"/>",
+                    headings.pause_before_ms, title, headings.pause_after_ms
+                ));
+            }
+            continue;
+        }
+        let mut l = re_blockquote.replace(line, "").into_owned();
+        l = re_bullet.replace(&l, "").into_owned();
+        l = re_numbered.replace(&l, "").into_owned();
+        let body = inline_to_ssml(l.trim(), lexicon);
+        if !body.is_empty() {
+            out.push(format!(
+                "{}<break time=\"{}ms\"/>",
+                body, PARAGRAPH_BREAK_MS
+            ));
+        }
+    }
+    out.join("\n")
+}
+
+/// Wrap an SSML body (or a chunk of one) in a `<speak>` document.
+pub fn wrap_ssml_document(body: &str) -> String {
+    format!("<speak>{}</speak>", body)
+}
+
+/// The text an SSML fragment speaks as written: tags removed (a `<sub>` keeps its written form)
+/// and entities unescaped. Used for captions.
+pub fn ssml_to_text(ssml: &str) -> String {
+    let re_break = Regex::new(r"<break\b[^>]*/>").unwrap();
+    let re_tag = Regex::new(r"<[^>]+>").unwrap();
+    let text = re_break.replace_all(ssml, " ");
+    let text = re_tag.replace_all(&text, "");
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn inline_to_ssml(line: &str, lexicon: &Lexicon) -> String {
+    let re_code = Regex::new(r"`([^`]+)`").unwrap();
+    let re_strong = Regex::new(r"\*\*([^*]+?)\*\*|__([^_]+?)__").unwrap();
+    let re_em = Regex::new(r"\*([^*\s](?:[^*]*[^*\s])?)\*").unwrap();
+
+    let mut pieces = vec![Piece::Text(line.to_string())];
+    pieces = split_pieces(pieces, &re_code, |caps| code_to_ssml(&caps[1], lexicon));
+    pieces = split_pieces(pieces, &re_strong, |caps| {
+        let inner = caps
+            .get(1)
+            .or_else(|| caps.get(2))
+            .map_or("", |m| m.as_str());
+        format!(
+            "<emphasis level=\"strong\">{}</emphasis>",
+            text_to_ssml(inner, lexicon)
+        )
+    });
+    pieces = split_pieces(pieces, &re_em, |caps| {
+        format!(
+            "<emphasis level=\"moderate\">{}</emphasis>",
+            text_to_ssml(&caps[1], lexicon)
+        )
+    });
+    render(pieces, lexicon)
+}
+
+/// Inline code: lexicon terms keep their spoken form, short vowel-less identifiers such as
+/// `fn` or `Rc` are spelled out, anything else is read as ordinary text.
+fn code_to_ssml(code: &str, lexicon: &Lexicon) -> String {
+    let re_ident = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let in_lexicon = lexicon
+        .rules()
+        .any(|(re, _)| re.find(code).is_some_and(|m| m.as_str() == code));
+    if !in_lexicon
+        && re_ident.is_match(code)
+        && code.len() <= 4
+        && !code.chars().any(|c| "aeiouAEIOU".contains(c))
+    {
+        return format!(
+            "<say-as interpret-as=\"characters\">{}</say-as>",
+            escape_xml(code)
+        );
+    }
+    text_to_ssml(code, lexicon)
+}
+
+/// Plain text: apply the lexicon, mark standalone integers as cardinals, escape the rest.
+fn text_to_ssml(text: &str, lexicon: &Lexicon) -> String {
+    render(vec![Piece::Text(text.to_string())], lexicon)
+}
+
+fn render(pieces: Vec<Piece>, lexicon: &Lexicon) -> String {
+    let re_number = Regex::new(r"\b\d+\b").unwrap();
+
+    let mut pieces = pieces;
+    for (re, entry) in lexicon.rules() {
+        pieces = split_pieces(pieces, re, |caps| lexicon_markup(caps, entry));
+    }
+    pieces = split_pieces(pieces, &re_number, |caps| {
+        format!("<say-as interpret-as=\"cardinal\">{}</say-as>", &caps[0])
+    });
+
+    let mut out = String::new();
+    for p in pieces {
+        match p {
+            Piece::Text(t) => out.push_str(&escape_xml(&t)),
+            Piece::Markup(m) => out.push_str(&m),
+        }
+    }
+    out
+}
+
+fn lexicon_markup(caps: &Captures, entry: &LexiconEntry) -> String {
+    let written = escape_xml(&caps[0]);
+    if let Some(ipa) = &entry.ipa {
+        return format!(
+            "<phoneme alphabet=\"ipa\" ph=\"{}\">{}</phoneme>",
+            escape_xml(ipa),
+            written
+        );
+    }
+    let mut spoken = String::new();
+    if entry.regex.is_some() {
+        caps.expand(&entry.say, &mut spoken);
+    } else {
+        spoken.push_str(&entry.say);
+    }
+    format!("<sub alias=\"{}\">{}</sub>", escape_xml(&spoken), written)
+}
+
+/// Split every `Text` piece on `re`, turning each match into finished markup.
+fn split_pieces(pieces: Vec<Piece>, re: &Regex, f: impl Fn(&Captures) -> String) -> Vec<Piece> {
+    let mut out = Vec::with_capacity(pieces.len());
+    for p in pieces {
+        let text = match p {
+            Piece::Text(t) => t,
+            markup => {
+                out.push(markup);
+                continue;
+            }
+        };
+        let mut last = 0usize;
+        for caps in re.captures_iter(&text) {
+            let m = caps.get(0).expect("match 0 always present");
+            if m.is_empty() {
+                continue;
+            }
+            if m.start() > last {
+                out.push(Piece::Text(text[last..m.start()].to_string()));
+            }
+            out.push(Piece::Markup(f(&caps)));
+            last = m.end();
+        }
+        if last < text.len() {
+            out.push(Piece::Text(text[last..].to_string()));
+        }
+    }
+    out
+}
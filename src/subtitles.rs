@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// One caption: text shown from `start` to `end` (seconds from the start of the chapter audio).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// SubRip (`.srt`) document for `cues`.
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            timestamp(cue.start, ','),
+            timestamp(cue.end, ','),
+            cue.text
+        ));
+    }
+    out
+}
+
+/// WebVTT (`.vtt`) document for `cues`.
+pub fn to_webvtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            timestamp(cue.start, '.'),
+            timestamp(cue.end, '.'),
+            escape_cue_text(&cue.text)
+        ));
+    }
+    out
+}
+
+/// Cue text is parsed as markup (`<b>`, `&amp;`), so `&` and `<` are escaped; escaping `>`
+/// also keeps a literal "-->" from being read as a timing arrow.
+fn escape_cue_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Write `<stem>.srt` and `<stem>.vtt` into `dir`.
+pub fn write_subtitles(dir: &Path, stem: &str, cues: &[Cue]) -> Result<()> {
+    for (ext, doc) in [("srt", to_srt(cues)), ("vtt", to_webvtt(cues))] {
+        let path = dir.join(format!("{}.{}", stem, ext));
+        fs::write(&path, doc).with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// `HH:MM:SS,mmm` (SRT) or `HH:MM:SS.mmm` (WebVTT).
+fn timestamp(secs: f64, decimal: char) -> String {
+    let total_ms = (secs.max(0.0) * 1000.0).round() as u64;
+    let ms = total_ms % 1000;
+    let s = total_ms / 1000 % 60;
+    let m = total_ms / 60_000 % 60;
+    let h = total_ms / 3_600_000;
+    format!("{:02}:{:02}:{:02}{}{:03}", h, m, s, decimal, ms)
+}
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One chapter link from mdBook's `SUMMARY.md`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SummaryEntry {
+    pub title: String,
+    /// Path relative to the book's `src/` directory; None for draft chapters.
+    pub path: Option<PathBuf>,
+    /// Section number such as `[3, 2]` for 3.2; None for prefix/suffix chapters.
+    pub number: Option<Vec<u32>>,
+}
+
+/// Book-level metadata from mdBook's `book.toml`.
+#[derive(Clone, Debug)]
+pub struct BookMeta {
+    pub title: String,
+    pub language: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+}
+
+impl BookMeta {
+    /// Read the `[book]` table of the `book.toml` next to `src_dir`, with fallbacks for a
+    /// missing file or fields.
+    pub fn from_book(src_dir: &Path) -> Self {
+        let book = src_dir
+            .parent()
+            .map(|root| root.join("book.toml"))
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|t| t.parse::<toml::Table>().ok())
+            .and_then(|t| t.get("book").and_then(|b| b.as_table()).cloned())
+            .unwrap_or_default();
+        let field = |key: &str| book.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        Self {
+            title: field("title").unwrap_or_else(|| "Audio Book".to_string()),
+            language: field("language").unwrap_or_else(|| "en".to_string()),
+            authors: book
+                .get("authors")
+                .and_then(|a| a.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            description: field("description"),
+        }
+    }
+}
+
+/// Table of contents parsed from `SUMMARY.md`, in reading order.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub entries: Vec<SummaryEntry>,
+}
+
+impl Summary {
+    /// Parse mdBook's summary format: plain `[Title](file.md)` lines are unnumbered
+    /// prefix/suffix chapters, `- [Title](file.md)` list items are numbered by nesting.
+    pub fn parse(text: &str) -> Self {
+        let re_item = Regex::new(r"^(\s*)[-*]\s+\[(.+?)\]\((.*?)\)").unwrap();
+        let re_plain = Regex::new(r"^\[(.+?)\]\((.*?)\)").unwrap();
+
+        let mut entries = Vec::new();
+        let mut counters: Vec<u32> = Vec::new();
+        let mut indents: Vec<usize> = Vec::new();
+        for line in text.lines() {
+            if let Some(caps) = re_item.captures(line) {
+                let indent = caps[1].replace('\t', "    ").len();
+                while indents.last().is_some_and(|&i| indent < i) {
+                    indents.pop();
+                    counters.pop();
+                }
+                if indents.last().is_none_or(|&i| indent > i) {
+                    indents.push(indent);
+                    counters.push(0);
+                }
+                if let Some(last) = counters.last_mut() {
+                    *last += 1;
+                }
+                entries.push(SummaryEntry {
+                    title: caps[2].trim().to_string(),
+                    path: link_path(&caps[3]),
+                    number: Some(counters.clone()),
+                });
+            } else if let Some(caps) = re_plain.captures(line.trim_start()) {
+                entries.push(SummaryEntry {
+                    title: caps[1].trim().to_string(),
+                    path: link_path(&caps[2]),
+                    number: None,
+                });
+            }
+        }
+        Self { entries }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Load `SUMMARY.md` from the directory holding `chapter`, if there is one.
+    pub fn for_chapter(chapter: &Path) -> Option<Self> {
+        let dir = chapter.parent().unwrap_or_else(|| Path::new("."));
+        let p = dir.join("SUMMARY.md");
+        if p.exists() {
+            Self::load(&p).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Find the entry for a chapter file, matching on its path relative to `src/`
+    /// (or on the file name when the chapter path is elsewhere).
+    pub fn find(&self, chapter: &Path) -> Option<&SummaryEntry> {
+        self.entries.iter().find(|e| {
+            e.path
+                .as_ref()
+                .is_some_and(|p| chapter.ends_with(p) || p.file_name() == chapter.file_name())
+        })
+    }
+}
+
+fn link_path(target: &str) -> Option<PathBuf> {
+    let t = target.trim();
+    if t.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(t.split('#').next().unwrap_or(t)))
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use regex::Regex;
+use std::time::Instant;
+
+use crate::diagnostics::{Diagnostics, Strictness};
+use crate::normalize::spell_symbols;
+use crate::tts::GeminiClient;
+
+/// A parsed table: optional header cells plus body rows, all as plain cell text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Replace GFM pipe tables and HTML `<table>` elements with spoken sentences.
+///
+/// Each row becomes `Row N: header value, header value, ...`. Tables with more than
+/// `llm_row_threshold` body rows (when set) are summarized by the model instead, since
+/// reading out a long appendix row by row is tiring to listen to.
+/// Returns the transformed text and the number of tables converted.
+pub async fn narrate_tables(
+    client: &GeminiClient,
+    input: &str,
+    llm_row_threshold: Option<usize>,
+    strictness: Strictness,
+    diagnostics: &mut Diagnostics,
+) -> Result<(String, usize)> {
+    let (with_html, html_tables) = extract_html_tables(input);
+    let lines: Vec<&str> = with_html.lines().collect();
+
+    let mut out = String::with_capacity(with_html.len());
+    let mut count = 0usize;
+    let mut in_fence = false;
+    let mut i = 0usize;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            out.push_str(line);
+            out.push('\n');
+            i += 1;
+            continue;
+        }
+
+        // Placeholder left behind by extract_html_tables
+        if let Some(idx) = html_placeholder_index(line) {
+            count += 1;
+            let table = &html_tables[idx];
+            let spoken =
+                speak_table(client, table, llm_row_threshold, strictness, diagnostics).await?;
+            out.push_str(&spoken);
+            out.push('\n');
+            i += 1;
+            continue;
+        }
+
+        if line.contains('|') && i + 1 < lines.len() && is_delimiter_row(lines[i + 1]) {
+            let header = split_row(line);
+            let mut rows = Vec::new();
+            let mut j = i + 2;
+            while j < lines.len() && lines[j].contains('|') && !lines[j].trim().is_empty() {
+                rows.push(split_row(lines[j]));
+                j += 1;
+            }
+            count += 1;
+            let table = Table { header, rows };
+            let spoken =
+                speak_table(client, &table, llm_row_threshold, strictness, diagnostics).await?;
+            out.push_str(&spoken);
+            out.push('\n');
+            i = j;
+            continue;
+        }
+
+        out.push_str(line);
+        out.push('\n');
+        i += 1;
+    }
+
+    Ok((out, count))
+}
+
+async fn speak_table(
+    client: &GeminiClient,
+    table: &Table,
+    llm_row_threshold: Option<usize>,
+    strictness: Strictness,
+    diagnostics: &mut Diagnostics,
+) -> Result<String> {
+    match llm_row_threshold {
+        Some(limit) if table.rows.len() > limit => {
+            println!("Summarizing table with {} row(s) via LLM", table.rows.len());
+            let t0 = Instant::now();
+            match client.summarize_table(&table_to_markdown(table)).await {
+                Ok(summary) => {
+                    println!("Table summary done in {:?}", t0.elapsed());
+                    Ok(summary.trim().to_string())
+                }
+                Err(e) => {
+                    diagnostics.report(
+                        strictness,
+                        format!("table summary failed, reading rows instead: {}", e),
+                    )?;
+                    Ok(table_to_sentences(table))
+                }
+            }
+        }
+        _ => Ok(table_to_sentences(table)),
+    }
+}
+
+/// Render a table as one spoken sentence per row, pairing each cell with its header.
+/// Symbols standing alone in a cell (an operator column) are read as words.
+pub fn table_to_sentences(table: &Table) -> String {
+    let headers: Vec<String> = table.header.iter().map(|h| spoken_header(h)).collect();
+    let mut lines = Vec::with_capacity(table.rows.len() + 1);
+
+    let named: Vec<&str> = headers
+        .iter()
+        .map(|h| h.as_str())
+        .filter(|h| !h.is_empty())
+        .collect();
+    let rows_word = if table.rows.len() == 1 { "row" } else { "rows" };
+    if named.is_empty() {
+        lines.push(format!("Table with {} {}.", table.rows.len(), rows_word));
+    } else {
+        lines.push(format!(
+            "Table with {} {} and columns {}.",
+            table.rows.len(),
+            rows_word,
+            named.join(", ")
+        ));
+    }
+
+    for (n, row) in table.rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.trim().is_empty())
+            .map(|(ci, c)| match headers.get(ci) {
+                Some(h) if !h.is_empty() => format!("{} {}", h, spell_symbols(c.trim())),
+                _ => spell_symbols(c.trim()),
+            })
+            .collect();
+        if cells.is_empty() {
+            continue;
+        }
+        lines.push(format!("Row {}: {}.", n + 1, cells.join(", ")));
+    }
+    lines.join("\n")
+}
+
+fn spoken_header(h: &str) -> String {
+    h.trim()
+        .trim_end_matches(['?', ':'])
+        .replace('`', "")
+        .to_lowercase()
+}
+
+fn table_to_markdown(table: &Table) -> String {
+    let mut out = String::new();
+    let row = |cells: &[String]| format!("| {} |\n", cells.join(" | "));
+    if !table.header.is_empty() {
+        out.push_str(&row(&table.header));
+        out.push_str(&row(&vec!["---".to_string(); table.header.len()]));
+    }
+    for r in &table.rows {
+        out.push_str(&row(r));
+    }
+    out
+}
+
+fn is_delimiter_row(line: &str) -> bool {
+    let re = Regex::new(r"^\s*\|?\s*:?-+:?\s*(\|\s*:?-+:?\s*)*\|?\s*$").unwrap();
+    line.contains('-') && re.is_match(line)
+}
+
+/// Split a GFM table row into trimmed cells, honouring `\|` escapes.
+fn split_row(line: &str) -> Vec<String> {
+    let mut t = line.trim();
+    t = t.strip_prefix('|').unwrap_or(t);
+    t = t.strip_suffix('|').unwrap_or(t);
+
+    let mut cells = Vec::new();
+    let mut cur = String::new();
+    let mut chars = t.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cur.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cur).trim().to_string()),
+            _ => cur.push(c),
+        }
+    }
+    cells.push(cur.trim().to_string());
+    cells
+}
+
+const HTML_TABLE_PLACEHOLDER: &str = "\u{0}html-table:";
+
+fn html_placeholder_index(line: &str) -> Option<usize> {
+    line.trim()
+        .strip_prefix(HTML_TABLE_PLACEHOLDER)
+        .and_then(|rest| rest.parse().ok())
+}
+
+/// Pull `<table>` elements out of the text, leaving a placeholder line for each.
+fn extract_html_tables(input: &str) -> (String, Vec<Table>) {
+    let re_table = Regex::new(r"(?is)<table\b.*?</table>").unwrap();
+    let re_row = Regex::new(r"(?is)<tr\b[^>]*>(.*?)</tr>").unwrap();
+    let re_cell = Regex::new(r"(?is)<t([hd])\b[^>]*>(.*?)</t[hd]>").unwrap();
+    let re_tags = Regex::new(r"</?[^>]+>").unwrap();
+    let re_ws = Regex::new(r"\s+").unwrap();
+
+    let mut tables = Vec::new();
+    let out = re_table
+        .replace_all(input, |caps: &regex::Captures| {
+            let mut table = Table::default();
+            for row in re_row.captures_iter(&caps[0]) {
+                let mut is_header = true;
+                let mut cells = Vec::new();
+                for cell in re_cell.captures_iter(&row[1]) {
+                    is_header &= &cell[1] == "h";
+                    let text = re_tags.replace_all(&cell[2], "");
+                    cells.push(re_ws.replace_all(text.trim(), " ").into_owned());
+                }
+                if is_header && table.header.is_empty() && table.rows.is_empty() {
+                    table.header = cells;
+                } else {
+                    table.rows.push(cells);
+                }
+            }
+            tables.push(table);
+            format!("\n{}{}\n", HTML_TABLE_PLACEHOLDER, tables.len() - 1)
+        })
+        .into_owned();
+    (out, tables)
+}
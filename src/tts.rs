@@ -4,7 +4,7 @@ use reqwest::StatusCode;
 use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
 use tokio::time::{Duration, sleep};
 
-use crate::audio::{is_raw_linear_pcm, parse_sample_rate, wrap_pcm_to_wav};
+use crate::chunking::{Budget, Measure};
 
 // Public list of available voices and their short descriptions.
 // The voice_name is what the TTS API expects.
@@ -41,10 +41,52 @@ pub const AVAILABLE_VOICES: &[(&str, &str)] = &[
     ("Sulafat", "Warm"),
 ];
 
+/// Input markup a TTS backend accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextFormat {
+    /// Plain text only; any markup would be read aloud.
+    Plain,
+    /// SSML documents wrapped in `<speak>`.
+    Ssml,
+}
+
+/// Input size limits a TTS backend declares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputLimits {
+    pub measure: Measure,
+    /// Hard per-request limit.
+    pub max: usize,
+    /// Size above which output quality suffers; chunks aim to stay under it.
+    pub preferred: usize,
+}
+
+impl InputLimits {
+    /// Budget left for narration text in each request once `overhead` (style prompt, SSML
+    /// wrapper) is accounted for.
+    pub fn chunk_budget(&self, overhead: &str) -> Result<Budget> {
+        let limit = self.preferred.min(self.max);
+        let used = self.measure.of(overhead);
+        if used >= limit {
+            return Err(anyhow!(
+                "request overhead uses {} of the {} {} input budget",
+                used,
+                limit,
+                self.measure.name()
+            ));
+        }
+        Ok(Budget {
+            measure: self.measure,
+            max: limit - used,
+        })
+    }
+}
+
 pub struct GeminiClient {
     http: reqwest::Client,
     api_key: String,
     base_url: String,
+    style_prompt: Option<String>,
+    streaming: bool,
 }
 
 impl GeminiClient {
@@ -56,15 +98,88 @@ impl GeminiClient {
             http,
             api_key,
             base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            style_prompt: None,
+            streaming: true,
         })
     }
 
+    /// Use `streamGenerateContent` for TTS (the default), or a single `generateContent`
+    /// response per chunk when false.
+    pub fn with_streaming(mut self, streaming: bool) -> Self {
+        self.streaming = streaming;
+        self
+    }
+
+    /// Natural-language style instruction sent ahead of every TTS input,
+    /// e.g. "Read aloud in a calm, friendly tone".
+    pub fn with_style_prompt(mut self, style_prompt: Option<String>) -> Self {
+        self.style_prompt = style_prompt;
+        self
+    }
+
+    /// Text sent before the narration in each TTS request.
+    pub fn prompt_prefix(&self) -> String {
+        match &self.style_prompt {
+            Some(style) => format!("{}:\n", style.trim_end().trim_end_matches(':')),
+            None => String::new(),
+        }
+    }
+
+    /// Gemini TTS takes natural-language text (optionally with style prompts), not SSML.
+    pub fn text_format(&self) -> TextFormat {
+        TextFormat::Plain
+    }
+
+    /// The TTS model accepts several thousand tokens per request, but long inputs drift in
+    /// pacing and voice, so chunks are kept to about a thousand tokens.
+    pub fn input_limits(&self) -> InputLimits {
+        InputLimits {
+            measure: Measure::Tokens,
+            max: 8000,
+            preferred: 1000,
+        }
+    }
+
     pub async fn summarize_code_block(&self, code: &str) -> Result<String> {
         let prompt = format!(
             "You are helping write an audio book. Convert the following code block to how a human would read it aloud. Say everything phonetically. No need to say opening curly brackets or semicolons. The following code is rust, so use that terminology
             \nCode block:\n{code}"
         );
+        self.generate_text(&prompt).await
+    }
+
+    pub async fn summarize_table(&self, table: &str) -> Result<String> {
+        let prompt = format!(
+            "You are helping write an audio book about the Rust programming language. The following table is too long to read out row by row. Summarize it in a few spoken sentences: what the columns mean, the overall pattern, and the most important entries. Say symbols as words. Do not use Markdown.
+            \nTable:\n{table}"
+        );
+        self.generate_text(&prompt).await
+    }
+
+    /// Describe an image for a listener. Raster images are sent as inline data; SVG is sent
+    /// as its XML source, since the API does not accept it as an image.
+    pub async fn describe_image(&self, image: &[u8], mime: &str, alt: &str) -> Result<String> {
+        let prompt = format!(
+            "You are helping write an audio book about the Rust programming language. Describe the following figure for a listener who cannot see it, in a few spoken sentences: what it shows, its parts and how they relate, and what the reader should take away. Do not start with \"This image\" and do not use Markdown. The figure's alt text is: \"{alt}\""
+        );
+        let figure = if mime == "image/svg+xml" {
+            serde_json::json!({ "text": format!("SVG source:\n{}", String::from_utf8_lossy(image)) })
+        } else {
+            serde_json::json!({
+                "inline_data": {
+                    "mime_type": mime,
+                    "data": base64::engine::general_purpose::STANDARD.encode(image)
+                }
+            })
+        };
+        self.generate(vec![serde_json::json!({ "text": prompt }), figure]).await
+    }
+
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.generate(vec![serde_json::json!({ "text": prompt })]).await
+    }
 
+    async fn generate(&self, parts: Vec<serde_json::Value>) -> Result<String> {
         let url = format!(
             "{}/models/{}:{}?key={}",
             self.base_url, "gemini-2.5-flash", "generateContent", self.api_key
@@ -72,7 +187,7 @@ impl GeminiClient {
 
         let body = serde_json::json!({
             "contents": [
-                { "role": "user", "parts": [ { "text": prompt } ] }
+                { "role": "user", "parts": parts }
             ],
             "generationConfig": {
                 "thinkingConfig": { "thinkingBudget": -1 }
@@ -89,39 +204,120 @@ impl GeminiClient {
         ))
     }
 
-    pub async fn tts_generate(&self, input_text: &str, voice_name: &str) -> Result<(Vec<u8>, String)> {
+    /// Synthesize one chunk, passing its audio to `on_audio` together with the audio's MIME
+    /// type, and return that MIME type. Streamed audio arrives in several fragments, a single
+    /// response in one.
+    pub async fn tts_generate<F>(
+        &self,
+        input_text: &str,
+        voice_name: &str,
+        mut on_audio: F,
+    ) -> Result<String>
+    where
+        F: FnMut(&[u8], &str) -> Result<()>,
+    {
+        if self.streaming {
+            return self.tts_stream(input_text, voice_name, on_audio).await;
+        }
+        let (raw, mime) = self.tts_single(input_text, voice_name).await?;
+        on_audio(&raw, &mime)?;
+        Ok(mime)
+    }
+
+    /// Synthesize one chunk with `streamGenerateContent`, passing each audio fragment to
+    /// `on_audio` as it arrives, and return the fragments' MIME type (raw PCM for Gemini,
+    /// e.g. `audio/L16;codec=pcm;rate=24000`). Only one server event is held in memory at a
+    /// time, and long chunks don't run into the single-response size and time limits.
+    ///
+    /// Failures before the first fragment are retried like other requests; once audio has
+    /// been handed out, an interrupted stream is an error, as a retry would repeat it.
+    pub async fn tts_stream<F>(
+        &self,
+        input_text: &str,
+        voice_name: &str,
+        mut on_audio: F,
+    ) -> Result<String>
+    where
+        F: FnMut(&[u8], &str) -> Result<()>,
+    {
         let url = format!(
-            "{}/models/{}:{}?key={}",
-            self.base_url, "gemini-2.5-pro-preview-tts", "generateContent", self.api_key
+            "{}/models/{}:{}?alt=sse&key={}",
+            self.base_url, "gemini-2.5-pro-preview-tts", "streamGenerateContent", self.api_key
         );
+        let body = self.tts_body(input_text, voice_name);
 
-        let body = serde_json::json!({
-            "contents": [
-                { "role": "user", "parts": [ { "text": input_text } ] }
-            ],
-            "generationConfig": {
-                "responseModalities": ["audio"],
-                "temperature": 1,
-                "speech_config": {
-                    "voice_config": {
-                        "prebuilt_voice_config": { "voice_name": voice_name }
+        let max_retries = 6;
+        let mut attempt = 0;
+        loop {
+            let mut resp = self.send_with_retries(&url, &body).await?;
+            let mut events = SseEvents::default();
+            let mut mime: Option<String> = None;
+            let mut failure: Option<anyhow::Error> = None;
+            'read: loop {
+                // At the end of the body, an event without a trailing blank line still counts
+                let (batch, done) = match resp.chunk().await {
+                    Ok(Some(c)) => (events.push(&c), false),
+                    Ok(None) => (events.finish().into_iter().collect(), true),
+                    Err(e) => {
+                        failure = Some(anyhow!("TTS stream interrupted: {}", e));
+                        break;
+                    }
+                };
+                for data in batch {
+                    let event: serde_json::Value = serde_json::from_str(&data)
+                        .with_context(|| format!("invalid TTS stream event: {}", data))?;
+                    if let Some(err) = event.get("error") {
+                        failure = Some(anyhow!("TTS stream error: {}", err));
+                        break 'read;
                     }
+                    let Some((data_b64, fragment_mime)) = extract_audio_inline_data(&event) else {
+                        continue;
+                    };
+                    let fragment = base64::engine::general_purpose::STANDARD
+                        .decode(data_b64)
+                        .context("failed to decode base64 audio")?;
+                    mime.get_or_insert_with(|| fragment_mime.to_string());
+                    on_audio(&fragment, fragment_mime)?;
+                }
+                if done {
+                    break;
                 }
             }
-        });
+
+            match (failure, mime) {
+                (None, Some(mime)) => return Ok(mime),
+                (None, None) => return Err(anyhow!("TTS stream ended without audio data")),
+                (Some(e), Some(_)) => return Err(e),
+                (Some(e), None) if attempt < max_retries => {
+                    let wait = compute_backoff(attempt, None);
+                    eprintln!(
+                        "warn: {}. retrying in {:?} (attempt {}/{})",
+                        e,
+                        wait,
+                        attempt + 1,
+                        max_retries
+                    );
+                    sleep(wait).await;
+                    attempt += 1;
+                }
+                (Some(e), None) => return Err(e),
+            }
+        }
+    }
+
+    /// Synthesize one chunk with a single `generateContent` response.
+    async fn tts_single(&self, input_text: &str, voice_name: &str) -> Result<(Vec<u8>, String)> {
+        let url = format!(
+            "{}/models/{}:{}?key={}",
+            self.base_url, "gemini-2.5-pro-preview-tts", "generateContent", self.api_key
+        );
+        let body = self.tts_body(input_text, voice_name);
 
         let json_val: serde_json::Value = self.post_json_with_retries(&url, &body).await?;
         if let Some((data_b64, mime)) = extract_audio_inline_data(&json_val) {
             let raw = base64::engine::general_purpose::STANDARD
                 .decode(data_b64)
                 .context("failed to decode base64 audio")?;
-
-            if is_raw_linear_pcm(&mime) {
-                let sr = parse_sample_rate(&mime).unwrap_or(24000);
-                let wav = wrap_pcm_to_wav(&raw, sr, 1, 16)?;
-                return Ok((wav, "audio/wav".to_string()));
-            }
-
             return Ok((raw, mime.to_string()));
         }
         Err(anyhow!(
@@ -130,11 +326,39 @@ impl GeminiClient {
         ))
     }
 
+    fn tts_body(&self, input_text: &str, voice_name: &str) -> serde_json::Value {
+        let text = format!("{}{}", self.prompt_prefix(), input_text);
+        serde_json::json!({
+            "contents": [
+                { "role": "user", "parts": [ { "text": text } ] }
+            ],
+            "generationConfig": {
+                "responseModalities": ["audio"],
+                "temperature": 1,
+                "speech_config": {
+                    "voice_config": {
+                        "prebuilt_voice_config": { "voice_name": voice_name }
+                    }
+                }
+            }
+        })
+    }
+
     async fn post_json_with_retries(
         &self,
         url: &str,
         body: &serde_json::Value,
     ) -> Result<serde_json::Value> {
+        let resp = self.send_with_retries(url, body).await?;
+        Ok(resp.json().await?)
+    }
+
+    /// POST `body` and return the first successful response, before its body is read.
+    async fn send_with_retries(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<reqwest::Response> {
         let max_retries = 6;
         let mut attempt = 0;
         loop {
@@ -147,10 +371,7 @@ impl GeminiClient {
                 .await;
 
             match resp {
-                Ok(r) if r.status().is_success() => {
-                    let json_val: serde_json::Value = r.json().await?;
-                    return Ok(json_val);
-                }
+                Ok(r) if r.status().is_success() => return Ok(r),
                 Ok(r) => {
                     let status = r.status();
                     let headers = r.headers().clone();
@@ -199,16 +420,75 @@ impl GeminiClient {
     }
 }
 
+/// Incremental parser for a `text/event-stream` body: bytes go in as they arrive and the
+/// `data:` payload of each completed event comes out.
+#[derive(Default)]
+struct SseEvents {
+    buf: Vec<u8>,
+    /// How much of `buf` has been searched for an event end without finding one.
+    scanned: usize,
+}
+
+impl SseEvents {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        // Events end with a blank line (LF or CRLF)
+        while let Some(end) = find_event_end(&self.buf, self.scanned) {
+            let raw: Vec<u8> = self.buf.drain(..end.0 + end.1).collect();
+            self.scanned = 0;
+            events.extend(event_data(&raw[..end.0]));
+        }
+        // A terminator may be split across pushes, so back up by its length minus one
+        self.scanned = self.buf.len().saturating_sub(3);
+        events
+    }
+
+    /// The data of a final event the stream ended without terminating, if any.
+    fn finish(&mut self) -> Option<String> {
+        self.scanned = 0;
+        let raw = std::mem::take(&mut self.buf);
+        event_data(&raw)
+    }
+}
+
+/// The joined `data:` lines of one event, if it has any.
+fn event_data(raw: &[u8]) -> Option<String> {
+    // Normalize CRLF so both line endings work
+    let text = String::from_utf8_lossy(raw).replace("\r\n", "\n");
+    let data: Vec<&str> = text
+        .lines()
+        .filter_map(|l| l.strip_prefix("data:"))
+        .map(|d| d.strip_prefix(' ').unwrap_or(d))
+        .collect();
+    (!data.is_empty()).then(|| data.join("\n"))
+}
+
+/// Position and length of the first event terminator (`\n\n` or `\r\n\r\n`) in `buf` at or
+/// after `from`.
+fn find_event_end(buf: &[u8], from: usize) -> Option<(usize, usize)> {
+    let rest = buf.get(from..)?;
+    let lf = rest.windows(2).position(|w| w == b"\n\n").map(|i| (from + i, 2));
+    let crlf = rest
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (from + i, 4));
+    match (lf, crlf) {
+        (Some(a), Some(b)) => Some(if a.0 < b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
 fn extract_first_text(v: &serde_json::Value) -> Option<&str> {
     let candidates = v.get("candidates")?.as_array()?;
     for c in candidates {
         let content = c.get("content")?;
         let parts = content.get("parts")?.as_array()?;
         for p in parts {
-            if let Some(t) = p.get("text").and_then(|t| t.as_str()) {
-                if !t.is_empty() {
-                    return Some(t);
-                }
+            if let Some(t) = p.get("text").and_then(|t| t.as_str())
+                && !t.is_empty()
+            {
+                return Some(t);
             }
         }
     }
@@ -258,14 +538,69 @@ fn compute_backoff(
     attempt: usize,
     retry_after_hdr: Option<&reqwest::header::HeaderValue>,
 ) -> Duration {
-    if let Some(hv) = retry_after_hdr {
-        if let Ok(s) = hv.to_str() {
-            if let Ok(secs) = s.trim().parse::<u64>() {
-                return Duration::from_secs(secs.max(1));
-            }
-        }
+    if let Some(hv) = retry_after_hdr
+        && let Ok(s) = hv.to_str()
+        && let Ok(secs) = s.trim().parse::<u64>()
+    {
+        return Duration::from_secs(secs.max(1));
     }
     let base_secs = 1u64.checked_shl(attempt as u32).unwrap_or(u64::MAX).min(60);
     let jitter_ms = ((attempt as u64 + 1) * 137) % 500;
     Duration::from_secs(base_secs) + Duration::from_millis(jitter_ms)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `body` to a parser in pieces of `size` bytes and collect every event's data.
+    fn parse_in_pieces(body: &[u8], size: usize) -> Vec<String> {
+        let mut events = SseEvents::default();
+        let mut out = Vec::new();
+        for piece in body.chunks(size) {
+            out.extend(events.push(piece));
+        }
+        out.extend(events.finish());
+        out
+    }
+
+    #[test]
+    fn sse_events_survive_any_split() {
+        let body = b"data: a\r\n\r\ndata: b\n\ndata: c1\ndata: c2\n\n";
+        for size in 1..=body.len() {
+            assert_eq!(
+                parse_in_pieces(body, size),
+                ["a", "b", "c1\nc2"],
+                "pieces of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn sse_final_event_without_blank_line_is_flushed() {
+        let body = b"data: first\n\ndata: last\n";
+        for size in 1..=body.len() {
+            assert_eq!(parse_in_pieces(body, size), ["first", "last"]);
+        }
+        assert_eq!(parse_in_pieces(b"data: only", 4), ["only"]);
+    }
+
+    #[test]
+    fn sse_ignores_events_without_data() {
+        let body = b": keep-alive\n\nevent: ping\n\ndata:no-space\n\n\n\n";
+        assert_eq!(parse_in_pieces(body, 3), ["no-space"]);
+        let mut events = SseEvents::default();
+        assert!(events.push(b"\n\n").is_empty());
+        assert_eq!(events.finish(), None);
+    }
+
+    #[test]
+    fn find_event_end_resumes_from_offset() {
+        let buf = b"data: a\n\ndata: b\r\n\r\n";
+        assert_eq!(find_event_end(buf, 0), Some((7, 2)));
+        assert_eq!(find_event_end(buf, 8), Some((16, 4)));
+        assert_eq!(find_event_end(buf, buf.len()), None);
+        assert_eq!(find_event_end(buf, buf.len() + 5), None);
+    }
+}